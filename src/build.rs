@@ -1,15 +1,40 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::fs::{copy, create_dir_all, read_dir, remove_dir, remove_dir_all, remove_file};
 use std::io::ErrorKind;
-use std::{fs, io, path::Path, str::FromStr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::{fs, io, path::Path, str::FromStr, thread};
 
 use log::{debug, error, info, warn};
+use serde_yaml::{Mapping, Value};
 
 use crate::cache;
-use crate::command::{Command, ExitStatus};
+use crate::collection::Collection;
+use crate::command::{self, ExitStatus};
+use crate::data;
 use crate::filepath::FilePath;
-use crate::rule::Rule;
+use crate::page::Page;
+use crate::parse::ParsedDataResult;
+use crate::rule::{self, Rule, Unmatched};
+use crate::site::Site;
+use crate::tempfile;
+use crate::template::{self, Templates};
 
-fn visit_dirs(dir: &Path) -> io::Result<Vec<FilePath>> {
+// Pages generated without a content file, e.g. for taxonomies
+const GENERATED_LIST: &str = "temp/generated";
+
+thread_local! {
+    static CURRENT_FILE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// The content file being built on the current thread, if any. Used to
+/// attribute log messages when building in parallel.
+pub fn current_file() -> Option<String> {
+    CURRENT_FILE.with(|f| f.borrow().clone())
+}
+
+pub fn visit_dirs(dir: &Path) -> io::Result<Vec<FilePath>> {
     let mut files = Vec::new();
     if dir.is_dir() {
         for entry in read_dir(dir)? {
@@ -26,29 +51,63 @@ fn visit_dirs(dir: &Path) -> io::Result<Vec<FilePath>> {
     Ok(files)
 }
 
-pub fn build(
-    rules: &[Rule],
-    pre_commands: Vec<Command>,
-    post_commands: Vec<Command>,
-    content: String,
-    output: String,
-    public: String,
-    force_recomp: bool,
-) -> bool {
+/// Settings for a build, mostly taken from the command line.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub content: String,
+    pub output: String,
+    pub public: String,
+    pub force: bool,
+    pub jobs: usize,
+}
+
+pub fn build(parsed: &ParsedDataResult, options: &Options) -> bool {
+    let ParsedDataResult {
+        rules,
+        unmatched,
+        pre_commands,
+        post_commands,
+        site,
+        collections,
+        taxonomies,
+        feeds,
+        sitemap,
+    } = parsed;
+
+    let Options {
+        content,
+        output,
+        public,
+        force: force_recomp,
+        jobs,
+    } = options.clone();
+
+    let env = command::environment(None, &output, None);
+    let log = command::log_path(tempfile::ROOT, "pre_commands");
+    _ = remove_file(&log);
     for command in pre_commands {
-        match command.exec(None, None) {
-            ExitStatus::Success(cmd) => {
-                debug!("Pre-command `{}` exited successfully", cmd);
+        match command.exec(None, None, &env, None, &log) {
+            ExitStatus::Success(cmd, captured) => {
+                debug!("Pre-command `{}` exited successfully{}", cmd, captured);
             }
 
             ExitStatus::InvalidCommand(cmd) => {
                 error!("Pre-command `{}` failed: not a valid command", cmd);
                 return false;
             }
-            ExitStatus::NonZero(cmd, code) => {
+            ExitStatus::NonZero(cmd, code, captured) => {
                 error!(
-                    "Pre-command `{}` failed: exited with non-zero code {}",
-                    cmd, code
+                    "Pre-command `{}` failed: exited with non-zero code {}{}",
+                    cmd, code, captured
+                );
+                return false;
+            }
+            ExitStatus::TimedOut(cmd, timeout, captured) => {
+                error!(
+                    "Pre-command `{}` failed: timed out after {}s{}",
+                    cmd,
+                    timeout.as_secs_f64(),
+                    captured
                 );
                 return false;
             }
@@ -71,24 +130,45 @@ pub fn build(
     let content_files = visit_dirs(Path::new(&content)).unwrap();
     let public_files = visit_dirs(Path::new(&public)).unwrap();
     let template_files = visit_dirs(Path::new("templates")).unwrap();
+    let data_files = visit_dirs(Path::new("data")).unwrap();
 
     let files = content_files
         .iter()
         .chain(public_files.iter())
         .chain(template_files.iter())
+        .chain(data_files.iter())
         .collect::<Vec<_>>();
 
     let mut file_cache = cache::read_cache(Path::new(".rssg-cache"));
-    let modified = cache::modified(&file_cache, &content, &public, &String::from("templates"))
-        .unwrap_or_default();
+    let modified = cache::modified(
+        &file_cache,
+        &content,
+        &public,
+        &String::from("templates"),
+        &String::from("data"),
+    )
+    .unwrap_or_default();
+
+    let mut deps = cache::read_deps(Path::new(".rssg-cache"));
+    let removed = file_cache
+        .keys()
+        .filter(|f| !files.contains(f))
+        .cloned()
+        .collect::<Vec<_>>();
 
-    let template_modified = modified.iter().any(|s| s.dir().starts_with("templates/"));
+    // Listing pages need rebuilding whenever a collection gains, loses or
+    // changes a page
+    let is_member = |f: &FilePath| collections.iter().any(|c| c.matches(f, &content));
+    let collection_modified = modified
+        .iter()
+        .chain(file_cache.keys().filter(|f| !files.contains(f)))
+        .any(is_member);
 
     info!("Building site");
     info!("Removing outdated files");
     if file_cache.is_empty() {
         _ = remove_dir_all(&output);
-        _ = remove_dir_all("temp");
+        clear_temp();
 
         if let Err(e) = create_dir_all(Path::new(&output)) {
             error!("Failed to create `{}`: {}", output, e);
@@ -124,37 +204,50 @@ pub fn build(
                     file_cache.remove(file);
 
                     continue;
-                } else {
-                    for rule in rules {
-                        if rule.matches(file) {
-                            if let Ok(path) = rule.out(file) {
-                                let path = path.strip_prefix(&content).prefix(&output);
-                                if let Err(e) = remove_file(Path::new(&path.full())) {
-                                    warn!(
-                                        "Failed to delete outdated file `{}`: {}",
-                                        path.full(),
-                                        e
-                                    );
-                                } else {
-                                    info!("Deleted outdated file `{}`", path.full());
-                                }
-
-                                if let Err(e) = remove_dir(Path::new(&path.dir())) {
-                                    warn!(
-                                        "Failed to delete outdated directory `{}/`: {}",
-                                        path.dir(),
-                                        e
-                                    );
-                                } else {
-                                    info!("Deleted outdated directory `{}`", path.dir());
-                                }
+                } else if file.full().starts_with(&content) && !deps.contains_key(file) {
+                    // Caches from before outputs were recorded; otherwise, the
+                    // outputs are removed after building
+                    for rule in rule::matching(rules, file, &content) {
+                        if !rule.has_output() {
+                            continue;
+                        }
+
+                        if let Ok(path) = rule.out(file, &Mapping::new()) {
+                            let path = path.strip_prefix(&content).prefix(&output);
+                            if let Err(e) = remove_file(Path::new(&path.full())) {
+                                warn!("Failed to delete outdated file `{}`: {}", path.full(), e);
+                            } else {
+                                info!("Deleted outdated file `{}`", path.full());
                             }
 
-                            file_cache.remove(file);
+                            if let Err(e) = remove_dir(Path::new(&path.dir())) {
+                                warn!(
+                                    "Failed to delete outdated directory `{}/`: {}",
+                                    path.dir(),
+                                    e
+                                );
+                            } else {
+                                info!("Deleted outdated directory `{}`", path.dir());
+                            }
+                        }
+                    }
 
-                            continue;
+                    // Unmatched files may have been copied as-is
+                    if *unmatched == Unmatched::Copy
+                        && !rules.iter().any(|r| r.matches(file, &content))
+                    {
+                        let path = file.clone().strip_prefix(&content).prefix(&output);
+                        if let Err(e) = remove_file(Path::new(&path.full())) {
+                            warn!("Failed to delete outdated file `{}`: {}", path.full(), e);
+                        } else {
+                            info!("Deleted outdated file `{}`", path.full());
+                            _ = remove_dir(Path::new(&path.dir()));
                         }
                     }
+
+                    file_cache.remove(file);
+                } else {
+                    file_cache.remove(file);
                 }
             }
         }
@@ -175,29 +268,241 @@ pub fn build(
     }
 
     info!("Generating data from `{}/`", content);
-    for file in &content_files {
-        if !(template_modified || modified.contains(file)) {
+
+    let (members, mut others): (Vec<_>, Vec<_>) = content_files.iter().partition(|f| is_member(f));
+
+    let Some(mut templates) = load_templates(site) else {
+        return false;
+    };
+
+    // Collections are first made from frontmatter alone, so that listing pages
+    // can be paginated before anything is built
+    let member_pages = pages_meta(rules, &members, &content);
+    set_collections(&mut templates, collections, &member_pages, &content);
+
+    // Collection members get built first, so that every other page can list
+    // them. Listing pages inside of a collection wait for the rest of it.
+    let listings = member_pages
+        .iter()
+        .filter(|p| p.paginates().is_some())
+        .map(|p| &p.source)
+        .collect::<HashSet<_>>();
+    let (members, mut listed): (Vec<_>, Vec<_>) =
+        members.into_iter().partition(|f| !listings.contains(f));
+    others.append(&mut listed);
+
+    // Pages reading `site` need rebuilding when a data file is added, too
+    let mut listing = data_files.iter().map(FilePath::full).collect::<Vec<_>>();
+    listing.sort();
+    let listing = listing.join("\n");
+
+    // A file needs rebuilding if it, the templates or data files it used, or
+    // the rules that apply to it have changed
+    let outdated = |file: &FilePath| {
+        modified.contains(file)
+            || deps.get(file).is_none_or(|d| {
+                d.config != config(parsed, &templates, &listing, file, &content)
+                    || d.files
+                        .iter()
+                        .any(|f| modified.contains(f) || removed.contains(f))
+            })
+    };
+
+    // Unmatched files are "rebuilt" every time, so that they're warned about on
+    // every build rather than just the first
+    let warns = |file: &FilePath| {
+        *unmatched == Unmatched::Warn && rule::matching(rules, file, &content).is_empty()
+    };
+
+    let mut members_to_build = Vec::with_capacity(members.len());
+    for file in &members {
+        if outdated(file) || warns(file) {
+            members_to_build.push(*file);
+        } else {
             debug!("Skipping file `{}`", file.full());
-            continue;
         }
+    }
 
-        debug!("Caching file `{}`", file.full());
-        cache::cache_file(Path::new(&file.full()), &mut file_cache);
+    // Pages listing a collection need rebuilding whenever it changes, and
+    // members listing one are built again once the rest of it has been
+    let lists = |file: &FilePath| lists_collections(rules, &templates, file, &content);
+    let mut others_to_build = Vec::with_capacity(others.len());
+    for file in others.iter().chain(members.iter().filter(|f| lists(f))) {
+        if outdated(file) || warns(file) || (collection_modified && lists(file)) {
+            others_to_build.push(*file);
+        } else {
+            debug!("Skipping file `{}`", file.full());
+        }
+    }
+
+    let Some(assets) = write_assets(rules, &output) else {
+        return false;
+    };
+
+    let Some(mut built) = build_files(
+        rules,
+        *unmatched,
+        &templates,
+        &members_to_build,
+        &content,
+        &output,
+        jobs,
+    ) else {
+        error!("Rule failed, aborting");
+        return false;
+    };
+
+    let mut built_pages = pages(rules, &members, &content);
+    built_pages.extend(
+        member_pages
+            .iter()
+            .filter(|p| listings.contains(&p.source))
+            .cloned(),
+    );
+    set_collections(&mut templates, collections, &built_pages, &content);
+
+    let Some(mut others_built) = build_files(
+        rules,
+        *unmatched,
+        &templates,
+        &others_to_build,
+        &content,
+        &output,
+        jobs,
+    ) else {
+        error!("Rule failed, aborting");
+        return false;
+    };
+    built.append(&mut others_built);
+
+    // Outputs a file no longer produces, e.g. because its rule was removed or
+    // its `output` changed
+    let mut stale = Vec::new();
+    for (file, outputs) in built {
+        let file_deps = cache::Deps {
+            config: config(parsed, &templates, &listing, &file, &content),
+            files: dependencies(rules, &templates, &data_files, &file, &content),
+            outputs,
+        };
+
+        if let Some(old) = deps.insert(file, file_deps) {
+            stale.extend(old.outputs);
+        }
+    }
 
-        info!("Building file `{}`", file.full());
-        for rule in rules {
-            if rule.matches(file) {
-                debug!("Found matching rule");
-                if !rule.exec(file.clone(), &content, &output) {
-                    error!("Rule failed, aborting");
+    deps.retain(|f, d| {
+        let exists = content_files.contains(f);
+        if !exists {
+            stale.append(&mut d.outputs);
+        }
+
+        exists
+    });
+
+    let current = deps
+        .values()
+        .flat_map(|d| &d.outputs)
+        .collect::<HashSet<_>>();
+    for path in stale.iter().filter(|f| !current.contains(f)) {
+        if let Err(e) = remove_file(Path::new(&path.full())) {
+            if e.kind() != ErrorKind::NotFound {
+                warn!("Failed to delete outdated file `{}`: {}", path.full(), e);
+            }
+        } else {
+            info!("Deleted outdated file `{}`", path.full());
+            _ = remove_dir(Path::new(&path.dir()));
+        }
+    }
+
+    let mut generated = assets;
+    let all_pages = if !taxonomies.is_empty() || !feeds.is_empty() || sitemap.is_some() {
+        pages(rules, &content_files.iter().collect::<Vec<_>>(), &content)
+    } else {
+        Vec::new()
+    };
+
+    if !taxonomies.is_empty() {
+        info!("Generating taxonomy pages");
+        let pages = &all_pages;
+
+        for taxonomy in taxonomies {
+            let members = match taxonomy.collection() {
+                Some(name) => collections
+                    .iter()
+                    .find(|c| c.name() == name)
+                    .map(|c| c.collect(pages, &content))
+                    .unwrap_or_default(),
+                None => pages.iter().collect(),
+            };
+
+            match taxonomy.generate(&members, &templates, &output) {
+                Ok(mut files) => generated.append(&mut files),
+                Err(e) => {
+                    error!(
+                        "Failed to generate pages for taxonomy `{}`: {}",
+                        taxonomy.name(),
+                        e
+                    );
                     return false;
                 }
+            }
+        }
+    }
+
+    if !feeds.is_empty() {
+        info!("Generating feeds");
+    }
 
-                break;
+    for feed in feeds {
+        match feed.generate(&all_pages, site, &content, &output) {
+            Ok(mut files) => generated.append(&mut files),
+            Err(e) => {
+                error!("Failed to generate feed: {}", e);
+                return false;
+            }
+        }
+    }
+
+    if let Some(sitemap) = sitemap {
+        info!("Generating sitemap");
+
+        let mut others = generated
+            .iter()
+            .map(|f| (f.clone().strip_prefix(&output), None))
+            .collect::<Vec<_>>();
+        others.extend(
+            public_files
+                .iter()
+                .map(|f| (f.clone().strip_prefix(&public), Some(f.clone()))),
+        );
+
+        let has_robots = others.iter().any(|(f, _)| f.full() == "robots.txt");
+        match sitemap.generate(&all_pages, &others, site, &output, has_robots) {
+            Ok(mut files) => generated.append(&mut files),
+            Err(e) => {
+                error!("Failed to generate sitemap: {}", e);
+                return false;
+            }
+        }
+    }
+
+    for file in cache::read_list(Path::new(GENERATED_LIST)) {
+        if generated.contains(&file) {
+            continue;
+        }
+
+        if let Err(e) = remove_file(file.full()) {
+            if e.kind() != ErrorKind::NotFound {
+                warn!("Failed to delete outdated file `{}`: {}", file.full(), e);
             }
+        } else {
+            info!("Deleted outdated file `{}`", file.full());
+            _ = remove_dir(file.dir());
         }
     }
 
+    cache::write_list(Path::new(GENERATED_LIST), &generated);
+
     info!("Site generation complete, copying `{}/`", public);
 
     for file in &public_files {
@@ -231,22 +536,33 @@ pub fn build(
     info!("Done building site, output at `{}/`", output);
 
     debug!("Writing cache");
-    cache::write_cache(Path::new(".rssg-cache"), file_cache);
+    cache::write_cache(Path::new(".rssg-cache"), file_cache, &deps);
 
+    let log = command::log_path(tempfile::ROOT, "post_commands");
+    _ = remove_file(&log);
     for command in post_commands {
-        match command.exec(None, None) {
-            ExitStatus::Success(cmd) => {
-                debug!("Post-command `{}` exited successfully", cmd);
+        match command.exec(None, None, &env, None, &log) {
+            ExitStatus::Success(cmd, captured) => {
+                debug!("Post-command `{}` exited successfully{}", cmd, captured);
             }
 
             ExitStatus::InvalidCommand(cmd) => {
                 error!("Post-command `{}` failed: not a valid command", cmd);
                 return false;
             }
-            ExitStatus::NonZero(cmd, code) => {
+            ExitStatus::NonZero(cmd, code, captured) => {
+                error!(
+                    "Post-command `{}` failed: exited with non-zero code {}{}",
+                    cmd, code, captured
+                );
+                return false;
+            }
+            ExitStatus::TimedOut(cmd, timeout, captured) => {
                 error!(
-                    "Post-command `{}` failed: exited with non-zero code {}",
-                    cmd, code
+                    "Post-command `{}` failed: timed out after {}s{}",
+                    cmd,
+                    timeout.as_secs_f64(),
+                    captured
                 );
                 return false;
             }
@@ -259,3 +575,843 @@ pub fn build(
 
     true
 }
+
+// A hash of everything in `rules.toml` that affects how `file` is built
+// (including collections, if it lists them), and of `listing` (the data files
+// there are) if it reads `site`
+fn config(
+    parsed: &ParsedDataResult,
+    templates: &Templates,
+    listing: &str,
+    file: &FilePath,
+    content: &str,
+) -> u128 {
+    let mut config = format!("{:?} {:?}", parsed.unmatched, parsed.site);
+    for rule in rule::matching(&parsed.rules, file, content) {
+        config.push_str(&rule.fingerprint());
+    }
+
+    if lists_collections(&parsed.rules, templates, file, content) {
+        for collection in &parsed.collections {
+            config.push_str(&collection.fingerprint());
+        }
+    }
+
+    if reads(&parsed.rules, templates, file, content, "site") {
+        config.push_str(listing);
+    }
+
+    cache::hash_str(&config)
+}
+
+// Whether `file` paginates a collection, or reads `collections`
+fn lists_collections(
+    rules: &[Rule],
+    templates: &Templates,
+    file: &FilePath,
+    content: &str,
+) -> bool {
+    reads(rules, templates, file, content, "collections")
+        || fs::read_to_string(file.full())
+            .is_ok_and(|d| rule::frontmatter(d).0.contains_key("paginate"))
+}
+
+// Whether building `file` reads the template variable `name`, either in its
+// rules' templates or in the file itself (which `{{data}}` pastes into them)
+fn reads(
+    rules: &[Rule],
+    templates: &Templates,
+    file: &FilePath,
+    content: &str,
+    name: &str,
+) -> bool {
+    let used = rule::matching(rules, file, content)
+        .into_iter()
+        .filter(|r| r.has_output())
+        .flat_map(|r| r.templates())
+        .collect::<Vec<_>>();
+
+    !used.is_empty()
+        && (used.iter().any(|t| templates.uses(t, name))
+            || fs::read_to_string(file.full()).is_ok_and(|d| template::mentions(&d, name)))
+}
+
+// The template and data files used to build `file`
+fn dependencies(
+    rules: &[Rule],
+    templates: &Templates,
+    data_files: &[FilePath],
+    file: &FilePath,
+    content: &str,
+) -> Vec<FilePath> {
+    let mut deps = Vec::new();
+    for rule in rule::matching(rules, file, content) {
+        if !rule.has_output() {
+            continue;
+        }
+
+        for template in rule.templates() {
+            for dep in templates.dependencies(template) {
+                if let Ok(dep) = FilePath::from_str(&dep) {
+                    let dep = dep.prefix("templates");
+                    if !deps.contains(&dep) {
+                        deps.push(dep);
+                    }
+                }
+            }
+        }
+    }
+
+    // Every template can see every data file
+    if reads(rules, templates, file, content, "site") {
+        deps.extend(data_files.iter().cloned());
+    }
+
+    deps
+}
+
+/// Loads every template, with the site configuration and data files exposed
+/// as `site`.
+pub fn load_templates(site: &Site) -> Option<Templates> {
+    let mut templates = match Templates::load("templates") {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Failed to load templates: {}", e);
+            return None;
+        }
+    };
+
+    // Configuration takes precedence over data files
+    match data::load("data") {
+        Ok(mut data) => {
+            data.extend(site.to_mapping());
+            templates.set_site(data);
+        }
+        Err(e) => {
+            error!("Failed to load data files: {}", e);
+            return None;
+        }
+    }
+
+    Some(templates)
+}
+
+/// Exposes every collection to templates, made up of `pages`.
+pub fn set_collections(
+    templates: &mut Templates,
+    collections: &[Collection],
+    pages: &[Page],
+    content: &str,
+) {
+    if collections.is_empty() {
+        return;
+    }
+
+    let mut data = Mapping::new();
+    for collection in collections {
+        let members = collection
+            .collect(pages, content)
+            .into_iter()
+            .map(Page::to_value)
+            .collect();
+
+        data.insert(collection.name().into(), Value::Sequence(members));
+    }
+
+    let pagination = collections
+        .iter()
+        .filter_map(|c| c.paginate().map(|p| (c.name().to_string(), p.clone())))
+        .collect();
+
+    templates.set_collections(data, pagination);
+}
+
+// Writes the files builtins need in the output directory, such as the
+// stylesheet for syntax highlighting. Returns the paths written.
+fn write_assets(rules: &[Rule], output: &str) -> Option<Vec<FilePath>> {
+    let builtins = rules
+        .iter()
+        .flat_map(|r| r.filters())
+        .filter_map(|f| f.as_builtin());
+
+    let mut written = Vec::new();
+    for (path, data) in builtins.filter_map(|b| b.asset()) {
+        let path = Path::new(output).join(path.trim_start_matches('/'));
+        if let Some(dir) = path.parent() {
+            if let Err(e) = create_dir_all(dir) {
+                error!("Failed to create directory for `{}`: {}", path.display(), e);
+                return None;
+            }
+        }
+
+        debug!("Writing `{}`", path.display());
+        if let Err(e) = fs::write(&path, data) {
+            error!("Failed to write `{}`: {}", path.display(), e);
+            return None;
+        }
+
+        if let Ok(path) = FilePath::from_str(&path.to_string_lossy()) {
+            if !written.contains(&path) {
+                written.push(path);
+            }
+        }
+    }
+
+    Some(written)
+}
+
+// Builds every file in `files` using up to `jobs` threads, returning the
+// outputs of each. Stops handing out new files as soon as one fails.
+fn build_files(
+    rules: &[Rule],
+    unmatched: Unmatched,
+    templates: &Templates,
+    files: &[&FilePath],
+    content: &String,
+    output: &String,
+    jobs: usize,
+) -> Option<Vec<(FilePath, Vec<FilePath>)>> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let built = Mutex::new(Vec::with_capacity(files.len()));
+
+    thread::scope(|s| {
+        for _ in 0..jobs.clamp(1, files.len().max(1)) {
+            s.spawn(|| {
+                while !failed.load(Ordering::Relaxed) {
+                    let Some(file) = files.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+
+                    match build_file(rules, unmatched, templates, file, content, output) {
+                        Some(outputs) => built.lock().unwrap().push(((*file).clone(), outputs)),
+                        None => failed.store(true, Ordering::Relaxed),
+                    }
+                }
+            });
+        }
+    });
+
+    if failed.load(Ordering::Relaxed) {
+        None
+    } else {
+        Some(built.into_inner().unwrap())
+    }
+}
+
+fn build_file(
+    rules: &[Rule],
+    unmatched: Unmatched,
+    templates: &Templates,
+    file: &FilePath,
+    content: &String,
+    output: &String,
+) -> Option<Vec<FilePath>> {
+    info!("Building file `{}`", file.full());
+    CURRENT_FILE.with(|f| *f.borrow_mut() = Some(file.full()));
+    _ = remove_file(command::log_path(tempfile::ROOT, &file.full()));
+
+    let matched = rule::matching(rules, file, content);
+    let outputs = if matched.is_empty() {
+        match unmatched {
+            Unmatched::Ignore => {
+                debug!("No rule matches, skipping");
+                Some(Vec::new())
+            }
+            Unmatched::Warn => {
+                warn!("No rule matches, skipping");
+                Some(Vec::new())
+            }
+            Unmatched::Error => {
+                error!("No rule matches");
+                None
+            }
+            Unmatched::Copy => {
+                debug!("No rule matches, copying");
+                copy_unmatched(file, content, output)
+            }
+        }
+    } else {
+        matched
+            .iter()
+            .map(|rule| {
+                debug!("Applying rule `{}`", rule.pattern());
+                rule.exec(
+                    file.clone(),
+                    content,
+                    output,
+                    tempfile::ROOT,
+                    templates,
+                    None,
+                )
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|outputs| outputs.concat())
+    };
+
+    CURRENT_FILE.with(|f| *f.borrow_mut() = None);
+    outputs
+}
+
+// Empties `temp/`, except for the logs, which the pre-commands may already have
+// written to
+fn clear_temp() {
+    let Ok(entries) = read_dir(tempfile::ROOT) else {
+        return;
+    };
+
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.file_name().is_some_and(|name| name == "logs") {
+            continue;
+        }
+
+        _ = if path.is_dir() {
+            remove_dir_all(&path)
+        } else {
+            remove_file(&path)
+        };
+    }
+}
+
+// Copies a file no rule matches straight to the output, like `public/` files.
+fn copy_unmatched(file: &FilePath, content: &String, output: &String) -> Option<Vec<FilePath>> {
+    let out = file.clone().strip_prefix(content).prefix(output);
+    if let Err(e) = create_dir_all(out.dir()) {
+        error!("Failed to create {}: {}", out.dir(), e);
+        return None;
+    }
+
+    if let Err(e) = copy(file.full(), out.full()) {
+        error!("Failed to copy {}: {}", file, e);
+        return None;
+    }
+
+    Some(vec![out])
+}
+
+/// Gathers the page information for already-built files.
+pub fn pages(rules: &[Rule], files: &[&FilePath], content: &String) -> Vec<Page> {
+    files
+        .iter()
+        .filter_map(|file| {
+            // The first output is the page's "real" one
+            rule::matching(rules, file, content)
+                .into_iter()
+                .find_map(|rule| rule.page(file, content))
+        })
+        .collect()
+}
+
+// Gathers the page information for files from their frontmatter alone, before
+// they're built.
+fn pages_meta(rules: &[Rule], files: &[&FilePath], content: &String) -> Vec<Page> {
+    files
+        .iter()
+        .filter_map(|file| {
+            rule::matching(rules, file, content)
+                .into_iter()
+                .find_map(|rule| rule.page_meta(file, content))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::env::{current_dir, set_current_dir, temp_dir};
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::path::PathBuf;
+    use std::sync::{Mutex, MutexGuard};
+
+    use super::*;
+    use crate::parse;
+
+    // Builds work in the current directory, so only one site can be open at a
+    // time
+    static CWD: Mutex<()> = Mutex::new(());
+
+    /// A site in a temporary directory, which is the current directory for as
+    /// long as this lives.
+    pub struct TestSite {
+        dir: PathBuf,
+        previous: PathBuf,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl TestSite {
+        pub fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let lock = CWD.lock().unwrap_or_else(|e| e.into_inner());
+            let dir = temp_dir().join(format!("rssg-test-{name}-{}", std::process::id()));
+            _ = remove_dir_all(&dir);
+            create_dir_all(&dir).unwrap();
+
+            let previous = current_dir().unwrap();
+            set_current_dir(&dir).unwrap();
+
+            let site = Self {
+                dir,
+                previous,
+                _lock: lock,
+            };
+            for (path, data) in files {
+                site.write(path, data);
+            }
+
+            site
+        }
+
+        pub fn write(&self, path: &str, data: &str) {
+            let path = self.dir.join(path);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            write(path, data).unwrap();
+        }
+
+        pub fn read(&self, path: &str) -> String {
+            read_to_string(self.dir.join(path)).unwrap_or_default()
+        }
+
+        pub fn exists(&self, path: &str) -> bool {
+            self.dir.join(path).exists()
+        }
+
+        /// Builds the site with `rules.toml`, returning whether it succeeded.
+        pub fn build(&self) -> bool {
+            let parsed = parse::parse(self.read("rules.toml"), None).unwrap();
+            build(
+                &parsed,
+                &Options {
+                    content: String::from("content"),
+                    output: String::from("output"),
+                    public: String::from("public"),
+                    force: false,
+                    jobs: 1,
+                },
+            )
+        }
+    }
+
+    impl Drop for TestSite {
+        fn drop(&mut self) {
+            _ = set_current_dir(&self.previous);
+            _ = remove_dir_all(&self.dir);
+        }
+    }
+
+    const BLOG: &str = r#"
+        filters = []
+
+        [[rules]]
+        rule = "content/blog/.*"
+        filters = []
+        templates = ["blog.html"]
+        output = "{dir}/{name}/index.html"
+
+        [[collections]]
+        name = "posts"
+        rule = "content/blog/.*"
+        sort = "title"
+        paginate_by = 2
+    "#;
+
+    const BLOG_TEMPLATE: &str = "{{#if paginator}}{{paginator.number}}/{{paginator.total}}:\
+        {{#each paginator.pages}} {{title}}{{/each}}{{else}}{{title}}{{/if}}";
+
+    fn post(title: &str) -> String {
+        format!("---\ntitle: {title}\n---\n")
+    }
+
+    #[test]
+    fn listing_inside_its_collection() {
+        let site = TestSite::new(
+            "listing",
+            &[
+                ("rules.toml", BLOG),
+                ("templates/blog.html", BLOG_TEMPLATE),
+                ("content/blog/index.md", "---\npaginate: posts\n---\n"),
+                ("content/blog/a.md", &post("A")),
+            ],
+        );
+
+        assert!(site.build());
+        assert_eq!(site.read("output/blog/index/index.html"), "1/1: A");
+        assert_eq!(site.read("output/blog/a/index.html"), "A");
+
+        site.write("content/blog/b.md", &post("B"));
+        assert!(site.build());
+        assert_eq!(site.read("output/blog/index/index.html"), "1/1: A B");
+    }
+
+    #[test]
+    fn paginated_listing_inside_its_collection() {
+        let site = TestSite::new(
+            "paginated",
+            &[
+                ("rules.toml", BLOG),
+                ("templates/blog.html", BLOG_TEMPLATE),
+                ("content/blog/index.md", "---\npaginate: posts\n---\n"),
+                ("content/blog/a.md", &post("A")),
+                ("content/blog/b.md", &post("B")),
+                ("content/blog/c.md", &post("C")),
+            ],
+        );
+
+        assert!(site.build());
+        assert_eq!(site.read("output/blog/index/index.html"), "1/2: A B");
+        assert_eq!(site.read("output/blog/index/page/2/index.html"), "2/2: C");
+
+        // The second page goes away along with the page that filled it
+        std::fs::remove_file("content/blog/c.md").unwrap();
+        assert!(site.build());
+        assert_eq!(site.read("output/blog/index/index.html"), "1/1: A B");
+        assert!(!site.exists("output/blog/index/page/2/index.html"));
+    }
+
+    #[test]
+    fn new_data_file_rebuilds_pages_reading_site() {
+        let site = TestSite::new(
+            "data",
+            &[
+                (
+                    "rules.toml",
+                    r#"
+                    filters = []
+
+                    [[rules]]
+                    rule = ".*"
+                    filters = []
+                    templates = ["page.html"]
+                    output = "{dir}/{name}.html"
+                    "#,
+                ),
+                ("templates/page.html", "[{{site.extra.note}}]"),
+                ("content/index.md", ""),
+            ],
+        );
+
+        assert!(site.build());
+        assert_eq!(site.read("output/index.html"), "[]");
+
+        site.write("data/extra.yaml", "note: hello");
+        assert!(site.build());
+        assert_eq!(site.read("output/index.html"), "[hello]");
+    }
+
+    #[test]
+    fn changed_collection_settings_rebuild_listings() {
+        let site = TestSite::new(
+            "settings",
+            &[
+                ("rules.toml", BLOG),
+                ("templates/blog.html", BLOG_TEMPLATE),
+                ("content/blog/index.md", "---\npaginate: posts\n---\n"),
+                ("content/blog/a.md", &post("A")),
+                ("content/blog/b.md", &post("B")),
+                ("content/blog/c.md", &post("C")),
+            ],
+        );
+
+        assert!(site.build());
+        assert_eq!(site.read("output/blog/index/index.html"), "1/2: A B");
+
+        site.write(
+            "rules.toml",
+            &BLOG.replace("paginate_by = 2", "paginate_by = 1"),
+        );
+        assert!(site.build());
+        assert_eq!(site.read("output/blog/index/index.html"), "1/3: A");
+        assert_eq!(site.read("output/blog/index/page/3/index.html"), "3/3: C");
+
+        site.write(
+            "rules.toml",
+            &BLOG.replace("paginate_by = 2", "paginate_by = 1\nreverse = true"),
+        );
+        assert!(site.build());
+        assert_eq!(site.read("output/blog/index/index.html"), "1/3: C");
+    }
+
+    #[test]
+    fn changed_taxonomy_settings_regenerate_terms() {
+        let rules = format!(
+            r#"{BLOG}
+            [[taxonomies]]
+            name = "tags"
+            collection = "posts"
+            template = "blog.html"
+            output = "tags/{{term}}/index.html"
+            paginate_by = 3
+            "#
+        );
+
+        let site = TestSite::new(
+            "taxonomy",
+            &[
+                ("rules.toml", &rules),
+                ("templates/blog.html", BLOG_TEMPLATE),
+                ("content/blog/a.md", "---\ntitle: A\ntags: [x]\n---\n"),
+                ("content/blog/b.md", "---\ntitle: B\ntags: [x]\n---\n"),
+            ],
+        );
+
+        assert!(site.build());
+        assert_eq!(site.read("output/tags/x/index.html"), "1/1: A B");
+
+        site.write(
+            "rules.toml",
+            &rules.replace("paginate_by = 3", "paginate_by = 1"),
+        );
+        assert!(site.build());
+        assert_eq!(site.read("output/tags/x/index.html"), "1/2: A");
+        assert_eq!(site.read("output/tags/x/page/2/index.html"), "2/2: B");
+    }
+
+    #[test]
+    fn templates_directory_with_other_files() {
+        let site = TestSite::new(
+            "templates",
+            &[
+                ("rules.toml", BLOG),
+                ("templates/blog.html", BLOG_TEMPLATE),
+                ("templates/draft.html", "{{#if}}"),
+                ("content/blog/a.md", &post("A")),
+            ],
+        );
+        write("templates/logo.png", [0x89, b'P', b'N', b'G', 0xff, 0xfe]).unwrap();
+
+        assert!(site.build());
+        assert_eq!(site.read("output/blog/a/index.html"), "A");
+
+        // The broken partial only fails pages that use it
+        site.write("templates/blog.html", "{{> draft}}");
+        assert!(!site.build());
+    }
+
+    #[test]
+    fn collection_changes_only_rebuild_listings() {
+        let site = TestSite::new(
+            "relist",
+            &[
+                (
+                    "rules.toml",
+                    r#"
+                        filters = []
+
+                        [[rules]]
+                        rule = "content/blog/.*"
+                        filters = []
+                        templates = ["post.html"]
+                        output = "{dir}/{name}.html"
+
+                        [[rules]]
+                        rule = "content/about.md"
+                        filters = []
+                        templates = ["about.html"]
+                        output = "{dir}/{name}.html"
+
+                        [[collections]]
+                        name = "posts"
+                        rule = "content/blog/.*"
+                        sort = "title"
+                    "#,
+                ),
+                (
+                    "templates/post.html",
+                    "{{title}}:{{#each collections.posts}} {{title}}{{/each}}",
+                ),
+                ("templates/about.html", "{{title}}"),
+                ("content/about.md", &post("About")),
+                ("content/blog/a.md", &post("A")),
+                ("content/blog/b.md", &post("B")),
+            ],
+        );
+
+        assert!(site.build());
+        assert_eq!(site.read("output/blog/a.html"), "A: A B");
+        assert_eq!(site.read("output/about.html"), "About");
+
+        site.write("output/about.html", "untouched");
+        site.write("content/blog/c.md", &post("C"));
+        assert!(site.build());
+        assert_eq!(site.read("output/blog/a.html"), "A: A B C");
+        assert_eq!(site.read("output/blog/c.html"), "C: A B C");
+        assert_eq!(site.read("output/about.html"), "untouched");
+    }
+
+    #[test]
+    fn stale_highlight_stylesheet_is_removed() {
+        let rules = r#"
+            [[filters]]
+            name = "highlight"
+            builtin = "highlight"
+            outfile = "{dir}/{name}.html"
+            css = "style/code.css"
+
+            [[rules]]
+            rule = "content/.*"
+            filters = ["highlight"]
+            templates = []
+            output = "{dir}/{name}.html"
+        "#;
+        let site = TestSite::new(
+            "stylesheet",
+            &[("rules.toml", rules), ("content/a.html", "<p>A</p>")],
+        );
+
+        assert!(site.build());
+        assert!(site.exists("output/style/code.css"));
+
+        site.write("rules.toml", &rules.replace("style/code.css", "code.css"));
+        assert!(site.build());
+        assert!(site.exists("output/code.css"));
+        assert!(!site.exists("output/style/code.css"));
+    }
+
+    #[test]
+    fn every_command_is_logged() {
+        let site = TestSite::new(
+            "logs",
+            &[
+                (
+                    "rules.toml",
+                    r#"
+                    log_commands = true
+                    pre_commands = ["echo before"]
+                    post_commands = ["echo after"]
+                    filters = []
+
+                    [[rules]]
+                    rule = "content/.*"
+                    filters = [{command = "echo filtering {name}"}]
+                    templates = []
+                    "#,
+                ),
+                ("content/a.md", "a"),
+            ],
+        );
+
+        assert!(site.build());
+        assert_eq!(
+            site.read("temp/logs/pre_commands.log"),
+            "$ echo before\n  stdout:\n    before\n(exit status: 0)\n\n"
+        );
+        assert!(site
+            .read("temp/logs/post_commands.log")
+            .starts_with("$ echo after\n"));
+        assert!(site
+            .read("temp/logs/content/a.md.log")
+            .starts_with("$ echo filtering a\n"));
+
+        // Each build replaces the logs
+        assert!(site.build());
+        assert_eq!(
+            site.read("temp/logs/pre_commands.log").matches('$').count(),
+            1
+        );
+    }
+
+    #[test]
+    fn stdio_filters_chain() {
+        let rules = r#"
+            filters = []
+
+            [[rules]]
+            rule = "content/.*"
+            filters = [
+                {command = "sed s/colour/color/", stdio = true},
+                {command = "tr a-z A-Z", stdio = true},
+            ]
+            templates = []
+            output = "{dir}/{name}.txt"
+        "#;
+        let site = TestSite::new(
+            "stdio",
+            &[
+                ("rules.toml", rules),
+                ("content/a.md", "---\ntitle: A\n---\ncolour\n"),
+            ],
+        );
+
+        assert!(site.build());
+        assert_eq!(site.read("output/a.txt"), "COLOR");
+
+        // Regular filters in between get the file written out, and their
+        // outfile is streamed on
+        site.write(
+            "rules.toml",
+            &rules.replacen(
+                "stdio = true},\n",
+                "stdio = true},\n{command = \"rev {full} > {outfile}\", shell = true, outfile = \"{name}.rev\"},\n",
+                1,
+            ),
+        );
+        site.write("content/a.md", "---\ntitle: A\n---\ncolour!");
+        assert!(site.build());
+        assert_eq!(site.read("output/a.txt"), "!ROLOC");
+    }
+
+    #[test]
+    fn command_environment() {
+        let site = TestSite::new(
+            "env",
+            &[
+                (
+                    "rules.toml",
+                    r#"
+                    pre_commands = [
+                        {command = "echo \"[$RSSG_SOURCE][$RSSG_VERSION]\" > pre.txt", shell = true},
+                    ]
+                    filters = []
+
+                    [site]
+                    base_url = "https://example.com/"
+
+                    [[rules]]
+                    rule = "content/.*"
+                    templates = []
+                    output = "{dir}/{name}.txt"
+
+                    [[rules.filters]]
+                    command = """printf '%s\n' "$THEME" "$BASE" "$PWD" "$RSSG_SOURCE" \
+                        "$RSSG_OUTPUT_DIR" "$RSSG_OUTFILE" "$RSSG_META_COVER_IMAGE" \
+                        "$RSSG_META_TAGS" {full} > {outfile}"""
+                    shell = true
+                    outfile = "{name}.txt"
+                    env = {THEME = "dark", BASE = "{base_url}"}
+                    cwd = "scripts"
+                    "#,
+                ),
+                ("scripts/.keep", ""),
+                (
+                    "content/a.md",
+                    "---\ntitle: A\ncover-image: Cover\ntags: [x, y]\n---\n",
+                ),
+            ],
+        );
+
+        assert!(site.build());
+        let cwd = current_dir().unwrap().display().to_string();
+        assert_eq!(
+            site.read("pre.txt"),
+            format!("[][{}]\n", env!("CARGO_PKG_VERSION"))
+        );
+
+        let out = site.read("output/a.txt");
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 9, "{out}");
+        assert_eq!(
+            lines[..5],
+            [
+                "dark".to_string(),
+                "https://example.com/".to_string(),
+                format!("{cwd}/scripts"),
+                format!("{cwd}/content/a.md"),
+                format!("{cwd}/output"),
+            ]
+        );
+        assert_eq!(lines[6..8], ["Cover", r#"["x","y"]"#]);
+
+        // The outfile and the working file are absolute too
+        let temp =
+            |line: &str, name| line.starts_with(&format!("{cwd}/temp/")) && line.ends_with(name);
+        assert!(temp(lines[5], "/a.txt"), "{out}");
+        assert!(temp(lines[8], "/a.md"), "{out}");
+    }
+}
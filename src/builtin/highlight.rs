@@ -0,0 +1,228 @@
+use std::sync::OnceLock;
+
+use fancy_regex::{Captures, Regex};
+use serde::Deserialize;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{
+    css_for_theme_with_class_style, start_highlighted_html_snippet,
+    styled_line_to_highlighted_html, ClassStyle, ClassedHTMLGenerator, IncludeBackground,
+};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+pub const DEFAULT_THEME: &str = "InspiredGitHub";
+pub const DEFAULT_CSS: &str = "highlight.css";
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn themes() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+fn code_block() -> &'static Regex {
+    static CODE_BLOCK: OnceLock<Regex> = OnceLock::new();
+    CODE_BLOCK.get_or_init(|| {
+        Regex::new(r#"(?s)<pre><code class="language-([^"\s]+)">(.*?)</code></pre>"#).unwrap()
+    })
+}
+
+/// Highlights `<pre><code class="language-x">` blocks, either with inline
+/// styles or with classes styled by a generated stylesheet.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Highlight {
+    theme: String,
+    inline: bool,
+    css: String,
+}
+
+impl Highlight {
+    /// Returns `None` if `theme` isn't one of the built-in themes.
+    pub fn new(theme: Option<String>, inline: bool, css: Option<String>) -> Option<Self> {
+        let theme = theme.unwrap_or_else(|| String::from(DEFAULT_THEME));
+        if !themes().themes.contains_key(&theme) {
+            return None;
+        }
+
+        Some(Self {
+            theme,
+            inline,
+            css: css.unwrap_or_else(|| String::from(DEFAULT_CSS)),
+        })
+    }
+
+    /// The names of every built-in theme.
+    pub fn themes() -> Vec<&'static str> {
+        themes().themes.keys().map(String::as_str).collect()
+    }
+
+    fn theme(&self) -> &'static Theme {
+        &themes().themes[&self.theme]
+    }
+
+    /// The stylesheet for class-based output, and where to put it (relative to
+    /// the output directory). Inline output doesn't need one.
+    pub fn stylesheet(&self) -> Option<(&str, String)> {
+        if self.inline {
+            return None;
+        }
+
+        css_for_theme_with_class_style(self.theme(), CLASS_STYLE)
+            .ok()
+            .map(|css| (self.css.as_str(), css))
+    }
+
+    /// Highlights every code block in `html` in a language we know. Anything
+    /// else is left alone.
+    pub fn render(&self, html: &str) -> Result<String, String> {
+        let mut error = None;
+        let result = code_block().replace_all(html, |caps: &Captures| {
+            let whole = caps[0].to_string();
+            let Some(syntax) = syntaxes().find_syntax_by_token(&caps[1]) else {
+                return whole;
+            };
+
+            let code = unescape(&caps[2]);
+            let highlighted = if self.inline {
+                self.inline(&code, &caps[1], syntax)
+            } else {
+                classed(&code, &caps[1], syntax)
+            };
+
+            match highlighted {
+                Ok(block) => block,
+                Err(e) => {
+                    error.get_or_insert(e);
+                    whole
+                }
+            }
+        });
+
+        match error {
+            Some(e) => Err(format!("Failed to highlight code: {e}")),
+            None => Ok(result.into_owned()),
+        }
+    }
+
+    fn inline(
+        &self,
+        code: &str,
+        lang: &str,
+        syntax: &SyntaxReference,
+    ) -> Result<String, syntect::Error> {
+        let theme = self.theme();
+        let (pre, bg) = start_highlighted_html_snippet(theme);
+        let mut out = pre.trim_end().to_string();
+        out.push_str(&format!(r#"<code class="language-{lang}">"#));
+
+        let mut highlighter = HighlightLines::new(syntax, theme);
+        for line in LinesWithEndings::from(code) {
+            let regions = highlighter.highlight_line(line, syntaxes())?;
+            out.push_str(&styled_line_to_highlighted_html(
+                &regions,
+                IncludeBackground::IfDifferent(bg),
+            )?);
+        }
+
+        out.push_str("</code></pre>");
+        Ok(out)
+    }
+}
+
+fn classed(code: &str, lang: &str, syntax: &SyntaxReference) -> Result<String, syntect::Error> {
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes(), CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        generator.parse_html_for_line_which_includes_newline(line)?;
+    }
+
+    Ok(format!(
+        r#"<pre class="hl-code"><code class="language-{lang}">{}</code></pre>"#,
+        generator.finalize()
+    ))
+}
+
+// Undoes the escaping done when the code block was rendered to HTML. The
+// highlighter escapes its output again.
+fn unescape(code: &str) -> String {
+    code.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: &str =
+        "<p>Code:</p><pre><code class=\"language-rust\">let x = &quot;&lt;a&gt;&quot;;\n</code></pre>";
+
+    #[test]
+    fn classes() {
+        let highlight = Highlight::new(None, false, None).unwrap();
+        assert_eq!(
+            highlight.render(CODE).unwrap(),
+            "<p>Code:</p><pre class=\"hl-code\"><code class=\"language-rust\">\
+             <span class=\"hl-source hl-rust\">\
+             <span class=\"hl-storage hl-type hl-rust\">let</span> x \
+             <span class=\"hl-keyword hl-operator hl-rust\">=</span> \
+             <span class=\"hl-string hl-quoted hl-double hl-rust\">\
+             <span class=\"hl-punctuation hl-definition hl-string hl-begin hl-rust\">&quot;</span>\
+             &lt;a&gt;\
+             <span class=\"hl-punctuation hl-definition hl-string hl-end hl-rust\">&quot;</span>\
+             </span><span class=\"hl-punctuation hl-terminator hl-rust\">;</span>\n\
+             </span></code></pre>"
+        );
+    }
+
+    #[test]
+    fn inline_styles() {
+        let highlight = Highlight::new(None, true, None).unwrap();
+        assert_eq!(
+            highlight.render(CODE).unwrap(),
+            "<p>Code:</p><pre style=\"background-color:#ffffff;\"><code class=\"language-rust\">\
+             <span style=\"font-weight:bold;color:#a71d5d;\">let</span>\
+             <span style=\"color:#323232;\"> x </span>\
+             <span style=\"font-weight:bold;color:#a71d5d;\">= </span>\
+             <span style=\"color:#183691;\">&quot;&lt;a&gt;&quot;</span>\
+             <span style=\"color:#323232;\">;\n</span></code></pre>"
+        );
+        assert!(highlight.stylesheet().is_none());
+    }
+
+    #[test]
+    fn unknown_languages_are_left_alone() {
+        let highlight = Highlight::new(None, false, None).unwrap();
+        let html = "<pre><code class=\"language-nope\">a &lt; b</code></pre>";
+        assert_eq!(highlight.render(html).unwrap(), html);
+    }
+
+    #[test]
+    fn stylesheet() {
+        let highlight = Highlight::new(None, false, None).unwrap();
+        let (path, css) = highlight.stylesheet().unwrap();
+        assert_eq!(path, DEFAULT_CSS);
+        assert!(css.contains(".hl-code {\n color: #323232;\n background-color: #ffffff;\n}"));
+        assert!(css.contains(".hl-string {\n color: #183691;\n}"));
+
+        let dark = Highlight::new(
+            Some("base16-ocean.dark".into()),
+            false,
+            Some("code.css".into()),
+        )
+        .unwrap();
+        let (path, dark_css) = dark.stylesheet().unwrap();
+        assert_eq!(path, "code.css");
+        assert_ne!(dark_css, css);
+
+        assert!(Highlight::new(Some("no-such-theme".into()), false, None).is_none());
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+
+use crate::helpers::slugify;
+
+/// Renders CommonMark to HTML, with tables, footnotes, strikethrough and task
+/// lists. Every heading gets an `id` (from `{#id}` if given, otherwise a slug
+/// of its text) so it can be linked to.
+pub fn render(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_HEADING_ATTRIBUTES;
+
+    let mut events = Parser::new_ext(markdown, options).collect::<Vec<_>>();
+    add_heading_ids(&mut events);
+
+    let mut out = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut out, events.into_iter());
+
+    out
+}
+
+fn add_heading_ids(events: &mut [Event]) {
+    let mut used: HashMap<String, usize> = HashMap::new();
+
+    let mut i = 0;
+    while i < events.len() {
+        let Event::Start(Tag::Heading { id: None, .. }) = &events[i] else {
+            i += 1;
+            continue;
+        };
+
+        let start = i;
+        let mut text = String::new();
+        while i < events.len() && !matches!(events[i], Event::End(TagEnd::Heading(_))) {
+            if let Event::Text(t) | Event::Code(t) = &events[i] {
+                text.push_str(t);
+            }
+
+            i += 1;
+        }
+
+        let mut slug = slugify(&text);
+        if slug.is_empty() {
+            slug = String::from("section");
+        }
+
+        // Repeated headings get numbered, like `intro`, `intro-1`, `intro-2`
+        let count = used.entry(slug.clone()).or_insert(0);
+        if *count > 0 {
+            slug = format!("{slug}-{count}");
+        }
+        *count += 1;
+
+        if let Event::Start(Tag::Heading { id, .. }) = &mut events[start] {
+            *id = Some(CowStr::from(slug));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables() {
+        assert_eq!(
+            render("| a | b |\n|---|:-:|\n| 1 | 2 |\n"),
+            "<table><thead><tr><th>a</th><th style=\"text-align: center\">b</th></tr></thead><tbody>\n\
+             <tr><td>1</td><td style=\"text-align: center\">2</td></tr>\n\
+             </tbody></table>\n"
+        );
+    }
+
+    #[test]
+    fn footnotes() {
+        assert_eq!(
+            render("Text[^1].\n\n[^1]: The note.\n"),
+            "<p>Text<sup class=\"footnote-reference\"><a href=\"#1\">1</a></sup>.</p>\n\
+             <div class=\"footnote-definition\" id=\"1\">\
+             <sup class=\"footnote-definition-label\">1</sup>\n\
+             <p>The note.</p>\n\
+             </div>\n"
+        );
+    }
+
+    #[test]
+    fn heading_ids() {
+        assert_eq!(
+            render("# Getting started\n## Intro\n## Intro\n## `code` & *more*\n"),
+            "<h1 id=\"getting-started\">Getting started</h1>\n\
+             <h2 id=\"intro\">Intro</h2>\n\
+             <h2 id=\"intro-1\">Intro</h2>\n\
+             <h2 id=\"code-more\"><code>code</code> &amp; <em>more</em></h2>\n"
+        );
+
+        // Given ids are kept, and headings without any text still get one
+        assert_eq!(
+            render("## Custom {#mine}\n# !!!\n"),
+            "<h2 id=\"mine\">Custom</h2>\n<h1 id=\"section\">!!!</h1>\n"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+mod highlight;
+mod markdown;
+
+use std::fs::{read_to_string, write};
+
+use serde::Deserialize;
+
+use crate::filepath::FilePath;
+
+pub use highlight::Highlight;
+
+/// A filter implemented inside of rssg, rather than by running a command.
+#[derive(Debug, Clone, Deserialize)]
+pub enum Builtin {
+    Markdown,
+    Highlight(Highlight),
+}
+
+impl Builtin {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Markdown => "markdown",
+            Self::Highlight(_) => "highlight",
+        }
+    }
+
+    /// A file this builtin needs in the output directory, and its contents.
+    pub fn asset(&self) -> Option<(&str, String)> {
+        match self {
+            Self::Highlight(highlight) => highlight.stylesheet(),
+            _ => None,
+        }
+    }
+
+    /// Runs the builtin on `input`, writing the result to `output`.
+    pub fn exec(&self, input: &FilePath, output: &FilePath) -> Result<(), String> {
+        let data =
+            read_to_string(input.full()).map_err(|e| format!("Failed to read {}: {}", input, e))?;
+
+        let result = match self {
+            Self::Markdown => markdown::render(&data),
+            Self::Highlight(highlight) => highlight.render(&data)?,
+        };
+
+        write(output.full(), result).map_err(|e| format!("Failed to write {}: {}", output, e))
+    }
+}
@@ -1,6 +1,8 @@
 use std::collections::HashMap;
+use std::fs;
 use std::fs::{read_dir, read_to_string, remove_file, File, OpenOptions};
 use std::io::Write;
+use std::time::SystemTime;
 use std::{io, path::Path, str::FromStr};
 
 use log::warn;
@@ -10,6 +12,10 @@ use crate::filepath::FilePath;
 
 // Caching is lower-priority, don't stop anything if it fails
 
+// Mark lines of dependencies and outputs, rather than file hashes
+const DEPS: &str = "deps  ";
+const OUTS: &str = "outs  ";
+
 fn visit_dirs(dir: &Path) -> io::Result<Vec<FilePath>> {
     let mut files = Vec::new();
     if dir.is_dir() {
@@ -27,17 +33,41 @@ fn visit_dirs(dir: &Path) -> io::Result<Vec<FilePath>> {
     Ok(files)
 }
 
+/// What a content file's outputs were built from, besides the file itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deps {
+    /// The hash of every rule (and setting) that applied to the file.
+    pub config: u128,
+    /// The templates and data files used.
+    pub files: Vec<FilePath>,
+    /// The files written to the output directory.
+    pub outputs: Vec<FilePath>,
+}
+
+// Folds the first 128 bits of a hash into a number
+fn digest(hasher: Sha256) -> u128 {
+    let hash = hasher.finalize();
+    let mut data: u128 = 0;
+    for (i, byte) in hash.iter().take(16).enumerate() {
+        data |= (*byte as u128) << (i * 8);
+    }
+
+    data
+}
+
+pub fn hash_str(data: &str) -> u128 {
+    let mut hasher = Sha256::new();
+    hasher.update(data.as_bytes());
+    digest(hasher)
+}
+
 pub fn hash_file(file: &Path) -> Option<(FilePath, u128)> {
     let mut hasher = Sha256::new();
     let mut f = File::open(file).ok()?;
 
     _ = io::copy(&mut f, &mut hasher).ok()?;
 
-    let hash = hasher.finalize();
-    let mut data: u128 = 0;
-    for (i, byte) in hash.iter().enumerate() {
-        data |= (*byte as u128).wrapping_shr(i as u32 * 8);
-    }
+    let data = digest(hasher);
 
     let file = match FilePath::from_str(&file.display().to_string()) {
         Ok(f) => f,
@@ -59,12 +89,28 @@ pub fn cache_file(file: &Path, cache: &mut HashMap<FilePath, u128>) {
     cache.insert(file, data);
 }
 
-pub fn write_cache(path: &Path, cache: HashMap<FilePath, u128>) {
+pub fn write_cache(path: &Path, cache: HashMap<FilePath, u128>, deps: &HashMap<FilePath, Deps>) {
     let mut cache_data = String::new();
     for (file, data) in cache {
         cache_data.push_str(format!("{file}  {data}\n").as_str());
     }
 
+    for (file, deps) in deps {
+        cache_data.push_str(&format!("{DEPS}{file}  {}", deps.config));
+        for dep in &deps.files {
+            cache_data.push_str(&format!("  {dep}"));
+        }
+        cache_data.push('\n');
+
+        if !deps.outputs.is_empty() {
+            cache_data.push_str(&format!("{OUTS}{file}"));
+            for out in &deps.outputs {
+                cache_data.push_str(&format!("  {out}"));
+            }
+            cache_data.push('\n');
+        }
+    }
+
     match OpenOptions::new()
         .write(true)
         .truncate(true)
@@ -99,7 +145,7 @@ pub fn read_cache(path: &Path) -> HashMap<FilePath, u128> {
 
     let cache_data = cache_data.split_terminator('\n').filter(|s| !s.is_empty());
 
-    for line in cache_data {
+    for line in cache_data.filter(|l| !l.starts_with(DEPS) && !l.starts_with(OUTS)) {
         let mut line = line.split_terminator("  ");
 
         let file = if let Some(Ok(f)) = line.next().map(FilePath::from_str) {
@@ -122,11 +168,59 @@ pub fn read_cache(path: &Path) -> HashMap<FilePath, u128> {
     cache
 }
 
+/// Reads the dependencies and outputs of every content file from the cache.
+pub fn read_deps(path: &Path) -> HashMap<FilePath, Deps> {
+    let mut deps = HashMap::new();
+    let cache_data = read_to_string(path).unwrap_or_default();
+
+    for line in cache_data.lines() {
+        if let Some(line) = line.strip_prefix(OUTS) {
+            // Always written right after the file's dependencies
+            let mut line = line.split_terminator("  ").map(FilePath::from_str);
+            match line
+                .next()
+                .and_then(Result::ok)
+                .and_then(|f| deps.get_mut(&f))
+            {
+                Some(Deps { outputs, .. }) => *outputs = line.filter_map(Result::ok).collect(),
+                None => warn!("Invalid output entry in .rssg-cache"),
+            }
+
+            continue;
+        } else if !line.starts_with(DEPS) {
+            continue;
+        }
+
+        let mut line = line.split_terminator("  ").skip(1);
+
+        let (Some(Ok(file)), Some(Ok(config))) = (
+            line.next().map(FilePath::from_str),
+            line.next().map(|s| s.parse::<u128>()),
+        ) else {
+            warn!("Invalid dependency entry in .rssg-cache");
+            continue;
+        };
+
+        let files = line.filter_map(|f| FilePath::from_str(f).ok()).collect();
+        deps.insert(
+            file,
+            Deps {
+                config,
+                files,
+                outputs: Vec::new(),
+            },
+        );
+    }
+
+    deps
+}
+
 pub fn modified(
     cache: &HashMap<FilePath, u128>,
     content: &String,
     public: &String,
     templates: &String,
+    data: &String,
 ) -> Option<Vec<FilePath>> {
     let mut files = visit_dirs(Path::new(content)).unwrap();
 
@@ -134,6 +228,8 @@ pub fn modified(
 
     files.append(&mut visit_dirs(Path::new(templates)).unwrap());
 
+    files.append(&mut visit_dirs(Path::new(data)).unwrap());
+
     let mut modified = Vec::with_capacity(files.len() / 2 + 1);
     for path in files {
         if let Some((file, data)) = hash_file(Path::new(&path.full())) {
@@ -145,3 +241,154 @@ pub fn modified(
 
     Some(modified)
 }
+
+/// A file's state, as seen by [`snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    pub hash: u128,
+}
+
+/// Hashes every file under the given paths, which may be directories or
+/// individual files. Missing paths are skipped.
+///
+/// Files whose modification time and size are the same as in `previous` keep
+/// their old hash instead of being read again.
+pub fn snapshot(paths: &[&str], previous: &HashMap<FilePath, Stamp>) -> HashMap<FilePath, Stamp> {
+    let mut snapshot = HashMap::new();
+    for path in paths {
+        let path = Path::new(path);
+        let files = if path.is_dir() {
+            visit_dirs(path).unwrap_or_default()
+        } else if let Ok(file) = FilePath::from_str(&path.display().to_string()) {
+            vec![file]
+        } else {
+            continue;
+        };
+
+        for file in files {
+            let Ok(meta) = fs::metadata(file.full()) else {
+                continue;
+            };
+
+            let (modified, len) = (meta.modified().ok(), meta.len());
+            let hash = match previous.get(&file) {
+                Some(old)
+                    if old.modified.is_some() && old.modified == modified && old.len == len =>
+                {
+                    Some(old.hash)
+                }
+                _ => hash_file(Path::new(&file.full())).map(|(_, hash)| hash),
+            };
+
+            if let Some(hash) = hash {
+                snapshot.insert(
+                    file,
+                    Stamp {
+                        modified,
+                        len,
+                        hash,
+                    },
+                );
+            }
+        }
+    }
+
+    snapshot
+}
+
+/// Reads a list of files, one per line, such as the files generated by the
+/// last build.
+pub fn read_list(path: &Path) -> Vec<FilePath> {
+    read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| FilePath::from_str(l).ok())
+        .collect()
+}
+
+pub fn write_list(path: &Path, list: &[FilePath]) {
+    let data = list.iter().map(|f| format!("{f}\n")).collect::<String>();
+    if let Err(e) = fs::write(path, data) {
+        warn!("Failed to write `{}`: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use super::*;
+
+    fn path(p: &str) -> FilePath {
+        FilePath::from_str(p).unwrap()
+    }
+
+    #[test]
+    fn deps_round_trip() {
+        let cache_path = temp_dir().join(format!("rssg-test-cache-{}", std::process::id()));
+
+        let hashes = HashMap::from([(path("content/a.md"), 1), (path("templates/a.html"), 2)]);
+        let deps = HashMap::from([
+            (
+                path("content/a.md"),
+                Deps {
+                    config: hash_str("config"),
+                    files: vec![path("templates/a.html"), path("data/x.yaml")],
+                    outputs: vec![
+                        path("output/a/index.html"),
+                        path("output/a/page/2/index.html"),
+                    ],
+                },
+            ),
+            (
+                path("content/b.md"),
+                Deps {
+                    config: 7,
+                    files: Vec::new(),
+                    outputs: Vec::new(),
+                },
+            ),
+        ]);
+
+        write_cache(&cache_path, hashes.clone(), &deps);
+        let (read_hashes, read) = (read_cache(&cache_path), read_deps(&cache_path));
+        _ = remove_file(&cache_path);
+
+        assert_eq!(read_hashes, hashes);
+        assert_eq!(read, deps);
+    }
+
+    #[test]
+    fn hash_str_differs() {
+        assert_eq!(hash_str("a"), hash_str("a"));
+        assert_ne!(hash_str("a"), hash_str("b"));
+        assert_ne!(
+            hash_str("data/a.yaml"),
+            hash_str("data/a.yaml\ndata/b.yaml")
+        );
+    }
+
+    #[test]
+    fn snapshot_only_hashes_changed_files() {
+        let dir = temp_dir().join(format!("rssg-test-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        fs::write(&file, "one").unwrap();
+
+        let paths = [dir.to_str().unwrap()];
+        let mut first = snapshot(&paths, &HashMap::new());
+        let key = first.keys().next().unwrap().clone();
+        assert_eq!(first[&key].hash, hash_str("one"));
+
+        // Unchanged metadata means the file isn't read again
+        first.get_mut(&key).unwrap().hash = 0;
+        assert_eq!(snapshot(&paths, &first)[&key].hash, 0);
+
+        fs::write(&file, "three").unwrap();
+        assert_eq!(snapshot(&paths, &first)[&key].hash, hash_str("three"));
+
+        _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,274 @@
+use std::cmp::Ordering;
+
+use chrono::{DateTime, FixedOffset};
+use serde_yaml::Value;
+
+use crate::filepath::FilePath;
+use crate::helpers::parse_date;
+use crate::page::Page;
+use crate::paginate::Paginate;
+use crate::rule::Pattern;
+
+/// A named set of pages, e.g. every blog post, for use in listing pages.
+#[derive(Debug, Clone)]
+pub struct Collection {
+    name: String,
+    rule: Pattern,
+    sort: Option<String>,
+    reverse: bool,
+    paginate: Option<Paginate>,
+}
+
+impl Collection {
+    pub fn new(
+        name: String,
+        rule: Pattern,
+        sort: Option<String>,
+        reverse: bool,
+        paginate: Option<Paginate>,
+    ) -> Self {
+        Self {
+            name,
+            rule,
+            sort,
+            reverse,
+            paginate,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn paginate(&self) -> Option<&Paginate> {
+        self.paginate.as_ref()
+    }
+
+    /// Everything about the collection that affects the pages listing it, so
+    /// that changes to it in `rules.toml` can be noticed.
+    pub fn fingerprint(&self) -> String {
+        format!(
+            "{} {} {:?} {:?} {} {:?}",
+            self.name,
+            self.rule.kind(),
+            self.rule.as_str(),
+            self.sort,
+            self.reverse,
+            self.paginate
+        )
+    }
+
+    /// Whether `filepath` belongs to the collection. Globs are relative to the
+    /// `content` directory.
+    pub fn matches(&self, filepath: &FilePath, content: &str) -> bool {
+        self.rule.is_match(filepath, content)
+    }
+
+    /// Picks out the pages belonging to this collection, in order. Listing
+    /// pages that paginate the collection aren't part of it.
+    pub fn collect<'a>(&self, pages: &'a [Page], content: &str) -> Vec<&'a Page> {
+        let mut members = pages
+            .iter()
+            .filter(|p| self.matches(&p.source, content) && p.paginates() != Some(self.name()))
+            .collect::<Vec<_>>();
+
+        let key = |p: &'a Page| self.sort.as_ref().and_then(|k| p.meta.get(k.as_str()));
+        members.sort_by(|a, b| {
+            let (a_key, b_key) = (key(a), key(b));
+            let ord = match (a_key, b_key) {
+                (Some(a), Some(b)) => compare(a, b),
+                _ => Ordering::Equal,
+            }
+            .then_with(|| a.source.full().cmp(&b.source.full()));
+
+            // Pages missing the sort key go last, even when reversed
+            a_key.is_none().cmp(&b_key.is_none()).then(if self.reverse {
+                ord.reverse()
+            } else {
+                ord
+            })
+        });
+
+        members
+    }
+}
+
+// Values of different types are ordered by type: numbers, then dates, then
+// other strings, then booleans, then anything else. This keeps the order total
+// when a key holds a mix of types.
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (sort_key(a), sort_key(b)) {
+        (SortKey::Number(a), SortKey::Number(b)) => a.total_cmp(&b),
+        (SortKey::Date(a), SortKey::Date(b)) => a.cmp(&b),
+        (SortKey::String(a), SortKey::String(b)) => a.cmp(b),
+        (SortKey::Bool(a), SortKey::Bool(b)) => a.cmp(&b),
+        (a, b) => a.rank().cmp(&b.rank()),
+    }
+}
+
+enum SortKey<'a> {
+    Number(f64),
+    Date(DateTime<FixedOffset>),
+    String(&'a str),
+    Bool(bool),
+    Other,
+}
+
+impl SortKey<'_> {
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Number(_) => 0,
+            Self::Date(_) => 1,
+            Self::String(_) => 2,
+            Self::Bool(_) => 3,
+            Self::Other => 4,
+        }
+    }
+}
+
+fn sort_key(value: &Value) -> SortKey<'_> {
+    match value {
+        Value::Number(n) => n.as_f64().map_or(SortKey::Other, SortKey::Number),
+        Value::String(s) => parse_date(s).map_or(SortKey::String(s), SortKey::Date),
+        Value::Bool(b) => SortKey::Bool(*b),
+        _ => SortKey::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use serde_yaml::Mapping;
+
+    use super::*;
+
+    fn page(source: &str) -> Page {
+        Page {
+            source: FilePath::from_str(source).unwrap(),
+            output: FilePath::from_str("index.html").unwrap(),
+            meta: Mapping::new(),
+            content: String::new(),
+        }
+    }
+
+    fn dated(source: &str, date: &str) -> Page {
+        let mut page = page(source);
+        page.meta.insert("date".into(), date.into());
+        page
+    }
+
+    fn sources(collection: &Collection, pages: &[Page]) -> Vec<String> {
+        collection
+            .collect(pages, "content")
+            .iter()
+            .map(|p| p.source.full())
+            .collect()
+    }
+
+    #[test]
+    fn regex_and_glob_patterns() {
+        let pages = [
+            page("content/blog/a.md"),
+            page("content/blog/2024/b.md"),
+            page("content/about.md"),
+        ];
+
+        let regex = Pattern::regex("content/blog/.*").unwrap();
+        let regex = Collection::new("posts".into(), regex, None, false, None);
+        assert_eq!(
+            sources(&regex, &pages),
+            ["content/blog/2024/b.md", "content/blog/a.md"]
+        );
+
+        let glob = Collection::new(
+            "posts".into(),
+            Pattern::glob("blog/*.md").unwrap(),
+            None,
+            false,
+            None,
+        );
+        assert_eq!(sources(&glob, &pages), ["content/blog/a.md"]);
+
+        let glob = Collection::new(
+            "posts".into(),
+            Pattern::glob("blog/**").unwrap(),
+            None,
+            false,
+            None,
+        );
+        assert_eq!(
+            sources(&glob, &pages),
+            ["content/blog/2024/b.md", "content/blog/a.md"]
+        );
+    }
+
+    #[test]
+    fn missing_sort_key_goes_last() {
+        let pages = [
+            page("content/blog/undated.md"),
+            dated("content/blog/old.md", "2023-01-01"),
+            dated("content/blog/new.md", "2024-06-01"),
+        ];
+        let posts = |reverse| {
+            let rule = Pattern::regex("content/blog/.*").unwrap();
+            Collection::new("posts".into(), rule, Some("date".into()), reverse, None)
+        };
+
+        assert_eq!(
+            sources(&posts(false), &pages),
+            [
+                "content/blog/old.md",
+                "content/blog/new.md",
+                "content/blog/undated.md"
+            ]
+        );
+        assert_eq!(
+            sources(&posts(true), &pages),
+            [
+                "content/blog/new.md",
+                "content/blog/old.md",
+                "content/blog/undated.md"
+            ]
+        );
+    }
+
+    #[test]
+    fn mixed_sort_key_types() {
+        let weights: [Value; 6] = [
+            "b".into(),
+            3.into(),
+            "2024-01-01".into(),
+            true.into(),
+            1.5.into(),
+            "a".into(),
+        ];
+        let mut pages = Vec::new();
+        for i in 0..60 {
+            let mut page = page(&format!("content/blog/{i:02}.md"));
+            page.meta
+                .insert("weight".into(), weights[i % weights.len()].clone());
+            pages.push(page);
+        }
+
+        let rule = Pattern::regex("content/blog/.*").unwrap();
+        let posts = Collection::new("posts".into(), rule, Some("weight".into()), false, None);
+        let sorted = posts.collect(&pages, "content");
+        let order = sorted
+            .iter()
+            .map(|p| p.meta["weight"].clone())
+            .collect::<Vec<_>>();
+
+        let expected: [Value; 6] = [
+            1.5.into(),
+            3.into(),
+            "2024-01-01".into(),
+            "a".into(),
+            "b".into(),
+            true.into(),
+        ];
+        for (chunk, value) in order.chunks(10).zip(expected.iter()) {
+            assert!(chunk.iter().all(|v: &Value| v == value), "{order:?}");
+        }
+    }
+}
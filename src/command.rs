@@ -1,96 +1,707 @@
-use std::process;
+use std::fmt::{self, Display};
+use std::fs::{create_dir_all, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{self, Stdio};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use fancy_regex::Regex;
-use log::debug;
+use fancy_regex::{Captures, Regex};
+use log::{debug, warn};
 use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
 
 use crate::filepath::FilePath;
 
-pub fn substitute(string: &str, path: &FilePath) -> String {
-    string
-        .replace("{full}", &path.full())
-        .replace("{dir}", &path.dir())
-        .replace("{name}", &path.name())
-        .replace("{ext}", &path.ext())
+// How long to keep reading a command's output after it exits
+const DRAIN: Duration = Duration::from_secs(1);
+
+// The path substitutions, in the order they're passed to shell scripts
+const PATHS: [&str; 6] = ["full", "dir", "name", "ext", "outfile", "parent"];
+
+fn placeholder_regex() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| {
+        Regex::new(r"\{(full|dir|name|ext|outfile|parent|meta\.([^}|]+)(?:\|([^}]*))?)\}").unwrap()
+    })
+}
+
+// The value of a path substitution, or `None` for `outfile` if there isn't one
+fn path_value(name: &str, path: &FilePath, outfile: Option<&FilePath>) -> Option<String> {
+    match name {
+        "full" => Some(path.full()),
+        "dir" => Some(path.dir()),
+        "name" => Some(path.name()),
+        "ext" => Some(path.ext()),
+        "parent" => Some(path.parent()),
+        _ => outfile.map(FilePath::full),
+    }
+}
+
+// The value of a `{meta.<key>|default}` placeholder
+fn meta_value(
+    caps: &Captures,
+    path: &FilePath,
+    outfile: Option<&FilePath>,
+    lookup: &impl Fn(&str) -> Option<String>,
+    warn: bool,
+) -> String {
+    if let Some(value) = lookup(&caps[2]) {
+        return value;
+    }
+
+    match caps.get(3).map(|d| d.as_str()) {
+        Some(default) if PATHS.contains(&default) => {
+            path_value(default, path, outfile).unwrap_or_default()
+        }
+        Some(default) => default.to_string(),
+        None => {
+            if warn {
+                warn!("Frontmatter has no `{}`, substituting nothing", &caps[2]);
+            }
+
+            String::new()
+        }
+    }
+}
+
+fn substitute_all(
+    string: &str,
+    path: &FilePath,
+    outfile: Option<&FilePath>,
+    lookup: &impl Fn(&str) -> Option<String>,
+    warn: bool,
+) -> String {
+    placeholder_regex()
+        .replace_all(string, |caps: &Captures| {
+            if caps.get(2).is_some() {
+                meta_value(caps, path, outfile, lookup, warn)
+            } else {
+                path_value(&caps[1], path, outfile).unwrap_or_else(|| caps[0].to_string())
+            }
+        })
+        .into_owned()
+}
+
+/// Replaces the path substitutions in `string` (`{full}`, `{dir}`, `{name}`,
+/// `{ext}`, `{parent}` and `{outfile}`, if given), and every `{meta.<key>}`
+/// with the frontmatter value of `key`, found with `lookup`. A default can
+/// follow a `|`: either a path substitution, as in `{meta.slug|name}`, or
+/// plain text, as in `{meta.lang|en}`.
+///
+/// Everything is replaced in one pass, so values are never substituted again.
+pub fn substitute_meta(
+    string: &str,
+    path: &FilePath,
+    outfile: Option<&FilePath>,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> String {
+    substitute_all(string, path, outfile, &lookup, true)
+}
+
+/// How a frontmatter value is written in substitutions and environment
+/// variables. Lists and maps are written as JSON.
+pub fn meta_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::Null => String::new(),
+        v => serde_json::to_string(v).unwrap_or_default(),
+    }
+}
+
+// Finds frontmatter values by their exact key
+fn lookup(meta: Option<&Mapping>) -> impl Fn(&str) -> Option<String> + '_ {
+    move |key| meta?.get(key).map(meta_string)
+}
+
+// The environment variable a frontmatter key is exported as
+fn meta_var(key: &str) -> String {
+    let key = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+
+    format!("RSSG_META_{key}")
 }
 
 #[derive(Debug)]
 pub enum ExitStatus {
-    Success(String),
+    Success(String, Captured),
 
     InvalidCommand(String),
 
-    NonZero(String, i32),
+    NonZero(String, i32, Captured),
+    TimedOut(String, Duration, Captured),
     Failed(String, std::io::Error),
 }
 
+/// What a command printed. Shown as an indented block after a log message, or
+/// nothing if it printed nothing.
+#[derive(Debug, Default)]
+pub struct Captured {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Display for Captured {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, text) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            let text = text.trim_end();
+            if !text.is_empty() {
+                write!(f, "\n  {name}:")?;
+                for line in text.lines() {
+                    write!(f, "\n    {line}")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The environment variables every command gets: `RSSG_VERSION`, and
+/// `RSSG_OUTPUT_DIR`. Commands run for a file also get `RSSG_SOURCE`, and its
+/// frontmatter as `RSSG_META_<KEY>`.
+pub fn environment(
+    source: Option<&FilePath>,
+    output: &str,
+    meta: Option<&Mapping>,
+) -> Vec<(String, String)> {
+    let mut env = vec![
+        (
+            "RSSG_VERSION".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        (
+            "RSSG_OUTPUT_DIR".to_string(),
+            absolute_dir(Path::new(output)),
+        ),
+    ];
+
+    if let Some(source) = source {
+        env.push(("RSSG_SOURCE".to_string(), absolute(source).full()));
+    }
+
+    for (key, value) in meta.into_iter().flatten() {
+        if let Some(key) = key.as_str() {
+            env.push((meta_var(key), meta_string(value)));
+        }
+    }
+
+    env
+}
+
+fn absolute_dir(path: &Path) -> String {
+    match std::env::current_dir() {
+        Ok(cwd) => cwd.join(path).display().to_string(),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+fn absolute(path: &FilePath) -> FilePath {
+    FilePath::from_str(&absolute_dir(Path::new(&path.full()))).unwrap_or_else(|_| path.clone())
+}
+
+/// Where the output of commands run for `file` is logged, if enabled, with
+/// temporary files in `temp`. Pre- and post-commands are logged as if for
+/// files named `pre_commands` and `post_commands`.
+pub fn log_path(temp: &str, file: &str) -> String {
+    format!("{temp}/logs/{file}.log")
+}
+
 /// A command to run, whether as part of a rule, pre-command, or post-command.
 #[derive(Debug, Clone, Deserialize)]
 pub struct Command {
     command: String,
+    shell: Option<String>,
+    log: bool,
+    timeout: Option<Duration>,
+    memory_limit: Option<u64>,
+    cpu_limit: Option<u64>,
+    env: Vec<(String, String)>,
+    cwd: Option<String>,
 }
 
 impl Command {
     pub fn new(command: String) -> Self {
-        Self { command }
+        Self {
+            command,
+            shell: None,
+            log: false,
+            timeout: None,
+            memory_limit: None,
+            cpu_limit: None,
+            env: Vec::new(),
+            cwd: None,
+        }
+    }
+
+    /// Sets extra environment variables for the command.
+    pub fn set_env(&mut self, env: Vec<(String, String)>) {
+        self.env = env;
+    }
+
+    /// Runs the command in another directory. Substituted paths are made
+    /// absolute, so they still work.
+    pub fn set_cwd(&mut self, cwd: Option<String>) {
+        self.cwd = cwd;
+    }
+
+    /// Kills the command, and everything it started, if it runs for longer
+    /// than `timeout`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Limits the command's memory, in megabytes, and CPU time, in seconds.
+    /// Only supported on Linux.
+    pub fn set_limits(&mut self, memory: Option<u64>, cpu: Option<u64>) {
+        self.memory_limit = memory;
+        self.cpu_limit = cpu;
+    }
+
+    /// Appends what the command prints to the log it's run with, see
+    /// [`log_path`].
+    pub fn set_log(&mut self, log: bool) {
+        self.log = log;
+    }
+
+    /// Runs the command as a script with the given shell, instead of splitting
+    /// it into arguments.
+    pub fn set_shell(&mut self, shell: Option<String>) {
+        self.shell = shell;
     }
 
     pub fn str(&self) -> &str {
         &self.command
     }
 
-    pub fn exec(&self, path: Option<&FilePath>, outfile: Option<FilePath>) -> ExitStatus {
-        // Split command on non-quoted whitespace, removing the quotes
-        let re = Regex::new("(\".*?(?<!\\\\)\"|[^ ])*").unwrap();
-        let quotes = Regex::new("^\"(.*)\"$").unwrap();
-
-        let subbed_command = if let Some(path) = path {
-            if let Some(out) = outfile {
-                substitute(&self.command, path).replace("{outfile}", &out.full())
-            } else {
-                substitute(&self.command, path)
-            }
+    // Paths are relative to the site, which a command with a `cwd` isn't in
+    fn resolve(&self, path: Option<&FilePath>) -> Option<FilePath> {
+        if self.cwd.is_some() {
+            path.map(absolute)
         } else {
-            self.command.clone()
+            path.cloned()
         }
-        .replace("{\\{", "{{");
-
-        let mut args = re.captures_iter(&subbed_command);
-        let mut command = match args.next() {
-            Some(Ok(c)) => c,
-            Some(Err(_)) | None => {
-                return ExitStatus::InvalidCommand(subbed_command);
-            }
-        }[0]
-        .to_string();
+    }
 
-        command = quotes.replace_all(&command, "$1").to_string();
+    /// The command line after substitutions, as it would be run, with
+    /// `{meta.<key>}` taken from `meta`.
+    pub fn substituted(
+        &self,
+        path: Option<&FilePath>,
+        outfile: Option<&FilePath>,
+        meta: Option<&Mapping>,
+    ) -> String {
+        let (path, outfile) = (self.resolve(path), self.resolve(outfile));
+        match &path {
+            Some(path) => substitute_meta(&self.command, path, outfile.as_ref(), lookup(meta)),
+            None => self.command.clone(),
+        }
+        .replace("{\\{", "{{")
+    }
 
-        let args = args
-            .map(|s| {
-                quotes
-                    .replace_all(&s.unwrap()[0], "$1")
-                    .replace("\\\"", "\"")
+    // The program and its arguments. The command is split before substituting,
+    // so a value with spaces in it stays one argument.
+    fn args(
+        &self,
+        path: Option<&FilePath>,
+        outfile: Option<&FilePath>,
+        meta: Option<&Mapping>,
+    ) -> Option<Vec<String>> {
+        let (path, outfile) = (self.resolve(path), self.resolve(outfile));
+        let lookup = lookup(meta);
+        let args = Self::split(&self.command)?
+            .into_iter()
+            .map(|arg| match &path {
+                Some(path) => substitute_all(&arg, path, outfile.as_ref(), &lookup, false),
+                None => arg,
             })
+            .map(|arg| arg.replace("{\\{", "{{"))
+            .collect();
+
+        Some(args)
+    }
+
+    // The command as a shell script, with paths and frontmatter replaced by
+    // positional parameters so they never need quoting
+    fn script(
+        &self,
+        path: Option<&FilePath>,
+        outfile: Option<&FilePath>,
+        meta: Option<&Mapping>,
+    ) -> (String, Vec<String>) {
+        let (path, outfile) = (self.resolve(path), self.resolve(outfile));
+        let Some(path) = path else {
+            return (self.command.replace("{\\{", "{{"), Vec::new());
+        };
+
+        let mut args = PATHS
+            .iter()
+            .map(|name| path_value(name, &path, outfile.as_ref()).unwrap_or_default())
             .collect::<Vec<_>>();
 
-        debug!("Running command `{}` (full `{}`)", command, subbed_command);
-        match process::Command::new(command).args(args).spawn() {
-            Ok(mut child) => match child.wait() {
-                Ok(code) => {
-                    if !code.success() {
-                        ExitStatus::NonZero(subbed_command, code.code().unwrap_or(1))
-                    } else {
-                        ExitStatus::Success(subbed_command)
-                    }
-                }
+        let lookup = lookup(meta);
+        let script = placeholder_regex()
+            .replace_all(&self.command, |caps: &Captures| {
+                let n = if caps.get(2).is_some() {
+                    args.push(meta_value(caps, &path, outfile.as_ref(), &lookup, false));
+                    args.len()
+                } else {
+                    PATHS.iter().position(|p| *p == &caps[1]).unwrap() + 1
+                };
 
-                Err(e) => ExitStatus::Failed(subbed_command, e),
-            },
+                format!("\"${{{n}}}\"")
+            })
+            .replace("{\\{", "{{");
+
+        (script, args)
+    }
+
+    // The process to run, or `None` if the command can't be split
+    fn process(
+        &self,
+        path: Option<&FilePath>,
+        outfile: Option<&FilePath>,
+        subbed_command: &str,
+        env: &[(String, String)],
+        meta: Option<&Mapping>,
+    ) -> Option<process::Command> {
+        let mut process = if let Some(shell) = &self.shell {
+            let (script, args) = self.script(path, outfile, meta);
+            debug!("Running `{}` with `{}`", script, shell);
+
+            // `$0` is the script's name, the paths come after
+            let mut process = process::Command::new(shell);
+            process.arg("-c").arg(script).arg("rssg").args(args);
+            process
+        } else {
+            let mut args = self.args(path, outfile, meta)?.into_iter();
+            let program = args.next()?;
+            debug!("Running command `{}` (full `{}`)", program, subbed_command);
+
+            let mut process = process::Command::new(program);
+            process.args(args);
+            process
+        };
+
+        process.envs(env.iter().cloned());
+        if let Some(outfile) = outfile {
+            process.env("RSSG_OUTFILE", absolute(outfile).full());
+        }
+
+        process.envs(self.env.iter().cloned());
+        if let Some(cwd) = &self.cwd {
+            process.current_dir(cwd);
+        }
+
+        Some(process)
+    }
+
+    /// Runs the command. `env` is added to its environment, see
+    /// [`environment`], and `meta` is the frontmatter for `{meta.<key>}`. Its
+    /// output is appended to `log`, if logging is on.
+    pub fn exec(
+        &self,
+        path: Option<&FilePath>,
+        outfile: Option<FilePath>,
+        env: &[(String, String)],
+        meta: Option<&Mapping>,
+        log: &str,
+    ) -> ExitStatus {
+        let subbed_command = self.substituted(path, outfile.as_ref(), meta);
+        let Some(process) = self.process(path, outfile.as_ref(), &subbed_command, env, meta) else {
+            return ExitStatus::InvalidCommand(subbed_command);
+        };
+
+        match self.run(process, None) {
+            Ok((status, stdout, stderr)) => {
+                let captured = Captured {
+                    stdout: String::from_utf8_lossy(&stdout).into_owned(),
+                    stderr: String::from_utf8_lossy(&stderr).into_owned(),
+                };
+
+                self.finish(subbed_command, status, captured, log)
+            }
 
             Err(e) => ExitStatus::Failed(subbed_command, e),
         }
     }
+
+    /// Runs the command with `input` as its stdin, returning its stdout.
+    pub fn pipe(
+        &self,
+        path: Option<&FilePath>,
+        input: Vec<u8>,
+        env: &[(String, String)],
+        meta: Option<&Mapping>,
+        log: &str,
+    ) -> (ExitStatus, Vec<u8>) {
+        let subbed_command = self.substituted(path, None, meta);
+        let Some(process) = self.process(path, None, &subbed_command, env, meta) else {
+            return (ExitStatus::InvalidCommand(subbed_command), Vec::new());
+        };
+
+        match self.run(process, Some(input)) {
+            Ok((status, stdout, stderr)) => {
+                // Stdout is the file, so only stderr is worth showing
+                let captured = Captured {
+                    stdout: String::new(),
+                    stderr: String::from_utf8_lossy(&stderr).into_owned(),
+                };
+
+                (self.finish(subbed_command, status, captured, log), stdout)
+            }
+
+            Err(e) => (ExitStatus::Failed(subbed_command, e), Vec::new()),
+        }
+    }
+
+    // Runs a process until it exits or times out (giving `None`), feeding it
+    // `input` and collecting its stdout and stderr
+    fn run(
+        &self,
+        mut process: process::Command,
+        input: Option<Vec<u8>>,
+    ) -> io::Result<(Option<process::ExitStatus>, Vec<u8>, Vec<u8>)> {
+        process.stdout(Stdio::piped()).stderr(Stdio::piped());
+        if input.is_some() {
+            process.stdin(Stdio::piped());
+        }
+
+        #[cfg(unix)]
+        if self.timeout.is_some() {
+            use std::os::unix::process::CommandExt;
+
+            // In its own process group, so that everything it starts can be
+            // killed along with it. That keeps it from the terminal, too.
+            process.process_group(0);
+            if input.is_none() {
+                process.stdin(Stdio::null());
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        limit(&mut process, self.memory_limit, self.cpu_limit);
+
+        let mut child = process.spawn()?;
+
+        // Everything is read and written from other threads, so that a child
+        // filling one pipe while we wait on another can't block us both
+        let writer = input.map(|input| {
+            let mut stdin = child.stdin.take().unwrap();
+            thread::spawn(move || stdin.write_all(&input))
+        });
+        let stdout = read_all(child.stdout.take());
+        let stderr = read_all(child.stderr.take());
+
+        let status = match self.timeout {
+            Some(timeout) => wait_timeout(&mut child, timeout)?,
+            None => Some(child.wait()?),
+        };
+
+        // Anything the child started in the background can keep its pipes
+        // open after it exits, so they're only read for a little longer
+        let deadline = Instant::now() + DRAIN;
+        let (stdout, stderr) = (stdout.finish(deadline), stderr.finish(deadline));
+
+        // A child that exits without reading everything closes the pipe, which
+        // isn't an error on our part
+        if let Some(Ok(Err(e))) = writer
+            .filter(thread::JoinHandle::is_finished)
+            .map(thread::JoinHandle::join)
+        {
+            debug!("Failed to write all of stdin: {}", e);
+        }
+
+        Ok((status, stdout, stderr))
+    }
+
+    fn finish(
+        &self,
+        subbed_command: String,
+        status: Option<process::ExitStatus>,
+        captured: Captured,
+        log: &str,
+    ) -> ExitStatus {
+        if self.log {
+            write_log(log, &subbed_command, status, &captured);
+        }
+
+        match status {
+            Some(status) if status.success() => ExitStatus::Success(subbed_command, captured),
+            Some(status) => {
+                ExitStatus::NonZero(subbed_command, status.code().unwrap_or(1), captured)
+            }
+            None => ExitStatus::TimedOut(subbed_command, self.timeout.unwrap(), captured),
+        }
+    }
+
+    // Splits a command into the program and its arguments, on whitespace
+    // outside of quotes and substitutions, removing the quotes. Returns `None`
+    // if there's no program.
+    fn split(command: &str) -> Option<Vec<String>> {
+        let re = Regex::new("(\".*?(?<!\\\\)\"|\\{[^}]*\\}|[^ ])*").unwrap();
+        let quotes = Regex::new("^\"(.*)\"$").unwrap();
+
+        let args = re
+            .captures_iter(command)
+            .map(|arg| arg.map(|a| a[0].to_string()))
+            .filter(|arg| !arg.as_ref().is_ok_and(String::is_empty))
+            .map(|arg| arg.map(|a| quotes.replace_all(&a, "$1").replace("\\\"", "\"")))
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+
+        (!args.is_empty()).then_some(args)
+    }
+}
+
+// What a command writes to a pipe, read on another thread
+struct Reader {
+    data: Arc<Mutex<Vec<u8>>>,
+    done: mpsc::Receiver<()>,
+}
+
+impl Reader {
+    // Everything read once the pipe closes, or whatever was read by `deadline`
+    // if it's still open then
+    fn finish(self, deadline: Instant) -> Vec<u8> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if self.done.recv_timeout(timeout).is_err() {
+            debug!("Something the command started still has its output open, not waiting for it");
+        }
+
+        std::mem::take(&mut *self.data.lock().unwrap())
+    }
+}
+
+fn read_all<R: Read + Send + 'static>(mut pipe: Option<R>) -> Reader {
+    let data = Arc::new(Mutex::new(Vec::new()));
+    let (sender, done) = mpsc::channel();
+
+    let shared = Arc::clone(&data);
+    thread::spawn(move || {
+        let mut buf = [0; 8192];
+        while let Some(pipe) = &mut pipe {
+            match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => shared.lock().unwrap().extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+
+        _ = sender.send(());
+    });
+
+    Reader { data, done }
+}
+
+// Waits for a child to exit, killing it (and its process group) if it takes
+// longer than `timeout`
+fn wait_timeout(
+    child: &mut process::Child,
+    timeout: Duration,
+) -> io::Result<Option<process::ExitStatus>> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        if start.elapsed() >= timeout {
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    #[cfg(unix)]
+    // SAFETY: only sends a signal. The child is its own group's leader, and
+    // hasn't been waited on, so the group can't belong to anything else.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+
+    #[cfg(not(unix))]
+    {
+        _ = child.kill();
+    }
+
+    child.wait()?;
+    Ok(None)
+}
+
+// Sets resource limits in the child, just before it runs
+#[cfg(target_os = "linux")]
+fn limit(process: &mut process::Command, memory: Option<u64>, cpu: Option<u64>) {
+    use std::os::unix::process::CommandExt;
+
+    if memory.is_none() && cpu.is_none() {
+        return;
+    }
+
+    let set = |resource, limit: u64| {
+        let limit = libc::rlimit {
+            rlim_cur: limit,
+            rlim_max: limit,
+        };
+
+        // SAFETY: `setrlimit` is async-signal-safe, and `limit` outlives
+        // the call
+        if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    };
+
+    // SAFETY: the closure only calls `setrlimit`, which is safe to do between
+    // `fork` and `exec`
+    unsafe {
+        process.pre_exec(move || {
+            if let Some(memory) = memory {
+                set(libc::RLIMIT_AS, memory.saturating_mul(1024 * 1024))?;
+            }
+
+            if let Some(cpu) = cpu {
+                set(libc::RLIMIT_CPU, cpu)?;
+            }
+
+            Ok(())
+        });
+    }
+}
+
+// Appends a command and its output to a log file, warning if that fails
+fn write_log(path: &str, command: &str, status: Option<process::ExitStatus>, captured: &Captured) {
+    let path = Path::new(path);
+    if let Some(parent) = path.parent() {
+        _ = create_dir_all(parent);
+    }
+
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut f| match status {
+            Some(status) => writeln!(f, "$ {command}{captured}\n({status})\n"),
+            None => writeln!(f, "$ {command}{captured}\n(timed out)\n"),
+        });
+
+    if let Err(e) = result {
+        warn!("Failed to write to log `{}`: {}", path.display(), e);
+    }
 }
 
 // struct CommandVisitor;
@@ -111,3 +722,177 @@ impl Command {
 //         Ok(Command::new(command))
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(p: &str) -> FilePath {
+        FilePath::from_str(p).unwrap()
+    }
+
+    fn meta(key: &str) -> Option<String> {
+        match key {
+            "title" => Some(String::from("Two Words")),
+            "tricky" => Some(String::from("{name}")),
+            _ => None,
+        }
+    }
+
+    fn frontmatter() -> Mapping {
+        serde_yaml::from_str("title: Two Words").unwrap()
+    }
+
+    #[test]
+    fn meta_fallbacks() {
+        let file = path("content/blog/post.md");
+        let out = path("temp/1/post.html");
+        let sub = |s: &str, outfile: Option<&FilePath>| substitute_meta(s, &file, outfile, meta);
+
+        assert_eq!(sub("{meta.title}", None), "Two Words");
+        assert_eq!(sub("{meta.title|name}", None), "Two Words");
+        assert_eq!(sub("{meta.slug|name}", None), "post");
+        assert_eq!(sub("{meta.slug|parent}", None), "blog");
+        assert_eq!(sub("{meta.lang|en}", None), "en");
+        assert_eq!(sub("{meta.lang|}", None), "");
+        assert_eq!(sub("[{meta.missing}]", None), "[]");
+        assert_eq!(sub("{meta.slug|outfile}", Some(&out)), "temp/1/post.html");
+        assert_eq!(sub("{meta.slug|outfile}", None), "");
+    }
+
+    #[test]
+    fn paths_and_meta_in_one_pass() {
+        let file = path("content/post.md");
+        let sub = |s: &str| substitute_meta(s, &file, None, meta);
+
+        assert_eq!(
+            sub("{dir}/{name}.{ext} {outfile}"),
+            "content/post.md {outfile}"
+        );
+        assert_eq!(sub("{meta.tricky}/{name}"), "{name}/post");
+    }
+
+    #[test]
+    fn meta_keys_are_exact() {
+        let meta: Mapping =
+            serde_yaml::from_str("Title: Upper\na-b: dash\na_b: underscore").unwrap();
+        let command = Command::new(String::from(
+            "echo {meta.Title} {meta.title|none} {meta.a-b} {meta.a_b}",
+        ));
+
+        assert_eq!(
+            command.substituted(Some(&path("content/post.md")), None, Some(&meta)),
+            "echo Upper none dash underscore"
+        );
+    }
+
+    #[test]
+    fn meta_values_stay_one_argument() {
+        let command = Command::new(String::from(
+            "sed \"s/a b/{meta.title}/\" s/x/{meta.title}/ {meta.none|some text} {full}",
+        ));
+
+        assert_eq!(
+            command
+                .args(
+                    Some(&path("content/my post.md")),
+                    None,
+                    Some(&frontmatter())
+                )
+                .unwrap(),
+            [
+                "sed",
+                "s/a b/Two Words/",
+                "s/x/Two Words/",
+                "some text",
+                "content/my post.md"
+            ]
+        );
+    }
+
+    #[test]
+    fn meta_values_are_shell_parameters() {
+        let mut command = Command::new(String::from(
+            "echo {meta.title} {name} {meta.slug|name} {meta.title}",
+        ));
+        command.set_shell(Some(String::from("/bin/sh")));
+
+        let (script, args) =
+            command.script(Some(&path("content/post.md")), None, Some(&frontmatter()));
+        assert_eq!(script, r#"echo "${7}" "${3}" "${8}" "${9}""#);
+        assert_eq!(args[6..], ["Two Words", "post", "Two Words"]);
+    }
+
+    #[test]
+    fn paths_are_shell_parameters() {
+        let mut command = Command::new(String::from("cat {full} > {outfile} # {\\{x}}"));
+        command.set_shell(Some(String::from("/bin/sh")));
+
+        let file = path("content/blog/it's a post.md");
+        let out = path("temp/1/it's a post.html");
+        let (script, args) = command.script(Some(&file), Some(&out), None);
+        assert_eq!(script, r#"cat "${1}" > "${5}" # {{x}}"#);
+        assert_eq!(
+            args,
+            [
+                "content/blog/it's a post.md",
+                "content/blog",
+                "it's a post",
+                "md",
+                "temp/1/it's a post.html",
+                "blog"
+            ]
+        );
+
+        // Without a file, nothing is substituted
+        let (script, args) = command.script(None, None, None);
+        assert_eq!(script, "cat {full} > {outfile} # {{x}}");
+        assert!(args.is_empty());
+    }
+
+    #[test]
+    fn shell_sees_arguments_unchanged() {
+        let mut command = Command::new(String::from("printf '%s|' {name} {meta.title} {ext}"));
+        command.set_shell(Some(String::from("/bin/sh")));
+
+        let file = path("content/$HOME `x` it's.md");
+        let output = command
+            .process(Some(&file), None, "", &[], Some(&frontmatter()))
+            .unwrap()
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "$HOME `x` it's|Two Words|md|"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeouts_kill_background_children() {
+        // The background `sleep` holds the pipes open, so reading would wait
+        // for it if it were left running
+        let mut command = Command::new(String::from("sleep 5 & sleep 5"));
+        command.set_shell(Some(String::from("/bin/sh")));
+        command.set_timeout(Some(Duration::from_millis(100)));
+
+        let start = Instant::now();
+        let status = command.exec(None, None, &[], None, "");
+        assert!(matches!(status, ExitStatus::TimedOut(..)), "{status:?}");
+        assert!(start.elapsed() < DRAIN, "took {:?}", start.elapsed());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn background_children_dont_hold_up_commands() {
+        let mut command = Command::new(String::from("sleep 5 & echo done"));
+        command.set_shell(Some(String::from("/bin/sh")));
+
+        let start = Instant::now();
+        match command.exec(None, None, &[], None, "") {
+            ExitStatus::Success(_, captured) => assert_eq!(captured.stdout, "done\n"),
+            status => panic!("{status:?}"),
+        }
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+}
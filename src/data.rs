@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::fs::{read_dir, read_to_string};
+use std::io;
+use std::path::Path;
+
+use log::{debug, warn};
+use serde_yaml::{Mapping, Value};
+
+#[derive(Debug)]
+pub enum DataErr {
+    FailedToRead(String, io::Error),
+
+    InvalidYaml(String, serde_yaml::Error),
+    InvalidToml(String, toml::de::Error),
+    InvalidJson(String, serde_json::Error),
+
+    SameName(String, String),
+}
+
+impl Display for DataErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FailedToRead(file, e) => write!(f, "Failed to read data file {file}: {e}"),
+
+            Self::InvalidYaml(file, e) => write!(f, "Invalid YAML in data file {file}: {e}"),
+            Self::InvalidToml(file, e) => write!(f, "Invalid TOML in data file {file}: {e}"),
+            Self::InvalidJson(file, e) => write!(f, "Invalid JSON in data file {file}: {e}"),
+
+            Self::SameName(a, b) => write!(
+                f,
+                "Data files {a} and {b} have the same name, so one would hide the other"
+            ),
+        }
+    }
+}
+
+impl Error for DataErr {}
+
+/// Loads every YAML, TOML and JSON file under `dir` into a mapping keyed by
+/// file name (minus extension). Subdirectories become nested mappings, so
+/// `data/social/links.toml` ends up at `social.links`.
+///
+/// Two files with the same name, like `foo.yaml` and `foo.json`, are an error.
+/// A file and a directory with the same name are merged, if the file holds a
+/// mapping. A missing directory is not an error, it just yields no data.
+pub fn load<P: AsRef<Path>>(dir: P) -> Result<Mapping, DataErr> {
+    let dir = dir.as_ref();
+    let mut data = Mapping::new();
+    if !dir.is_dir() {
+        return Ok(data);
+    }
+
+    let mut entries = read_dir(dir)
+        .map_err(|e| DataErr::FailedToRead(dir.display().to_string(), e))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| DataErr::FailedToRead(dir.display().to_string(), e))?;
+    entries.sort();
+
+    // The file each key came from, if any
+    let mut files: HashMap<String, &Path> = HashMap::new();
+    for path in &entries {
+        let Some(name) = path.file_stem().map(|n| n.to_string_lossy().into_owned()) else {
+            continue;
+        };
+
+        let value = if path.is_dir() {
+            Value::Mapping(load(path)?)
+        } else if let Some(value) = load_file(path)? {
+            value
+        } else {
+            warn!("Ignoring data file `{}` with unknown type", path.display());
+            continue;
+        };
+
+        debug!("Loaded data file `{}`", path.display());
+        let same_name = |other: &Path| {
+            DataErr::SameName(other.display().to_string(), path.display().to_string())
+        };
+
+        if !path.is_dir() {
+            if let Some(other) = files.insert(name.clone(), path) {
+                return Err(same_name(other));
+            }
+        }
+
+        match (data.get_mut(name.as_str()), value) {
+            // `foo.yaml` and `foo/` share a key, merge them
+            (Some(Value::Mapping(existing)), Value::Mapping(new)) => existing.extend(new),
+            (Some(_), _) if path.is_dir() => return Err(same_name(files[&name])),
+            (Some(_), _) => return Err(same_name(&dir.join(&name))),
+            (None, value) => {
+                data.insert(name.into(), value);
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+fn load_file(path: &Path) -> Result<Option<Value>, DataErr> {
+    let file = path.display().to_string();
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    if !matches!(ext.as_str(), "yaml" | "yml" | "toml" | "json") {
+        return Ok(None);
+    }
+
+    let data = read_to_string(path).map_err(|e| DataErr::FailedToRead(file.clone(), e))?;
+
+    let value = match ext.as_str() {
+        "yaml" | "yml" => serde_yaml::from_str(&data).map_err(|e| DataErr::InvalidYaml(file, e))?,
+        "toml" => {
+            let value: toml::Value =
+                toml::from_str(&data).map_err(|e| DataErr::InvalidToml(file.clone(), e))?;
+            serde_yaml::to_value(value).map_err(|e| DataErr::InvalidYaml(file, e))?
+        }
+        _ => {
+            let value: serde_json::Value =
+                serde_json::from_str(&data).map_err(|e| DataErr::InvalidJson(file.clone(), e))?;
+            serde_yaml::to_value(value).map_err(|e| DataErr::InvalidYaml(file, e))?
+        }
+    };
+
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    use super::*;
+
+    #[test]
+    fn same_names() {
+        let dir = std::env::temp_dir().join(format!("rssg-data-{}", std::process::id()));
+        create_dir_all(dir.join("social")).unwrap();
+        write(dir.join("social.yaml"), "github: me").unwrap();
+        write(dir.join("social/links.toml"), "mastodon = 'me'").unwrap();
+
+        let data = load(&dir).unwrap();
+        let social = data["social"].as_mapping().unwrap();
+        assert_eq!(social["github"], "me");
+        assert_eq!(social["links"]["mastodon"], "me");
+
+        write(dir.join("social.json"), r#"{"github": "you"}"#).unwrap();
+        let e = load(&dir).unwrap_err();
+        assert!(
+            matches!(&e, DataErr::SameName(a, b) if a.ends_with("social.json") && b.ends_with("social.yaml")),
+            "{e}"
+        );
+
+        // A file that isn't a mapping can't be merged with a directory
+        remove_dir_all(dir.join("social")).unwrap();
+        create_dir_all(dir.join("menu")).unwrap();
+        write(dir.join("menu.yaml"), "[a, b]").unwrap();
+        assert!(matches!(load(&dir), Err(DataErr::SameName(..))));
+
+        _ = remove_dir_all(&dir);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::builtin::Highlight;
+
 pub type ParseResult<T> = std::result::Result<T, ParseError>;
 
 #[derive(Debug, Clone)]
@@ -5,14 +7,42 @@ pub enum ParseError {
     // common
     MissingField(&'static str),
     BadArrayItem,
+    BadPaginateBy,
     TomlError(toml::de::Error),
 
     // Rule
     BadRegex(String),
+    BadGlob(String, globset::Error),
+    RuleAndGlob,
     NoSuchFilter(String),
+    BadUnmatched(String),
+    BadShell(String),
+    BadTimeout(String),
+    BadLimit(&'static str, String),
+    BadEnvTable,
+    BadEnv(String),
+    BadCwd,
 
     // Filter
     MissingFilterName,
+    NoSuchBuiltin(String),
+    NoSuchTheme(String),
+    BuiltinStdio(String),
+
+    // Collection
+    DuplicateCollection(String),
+    NoSuchCollection(String),
+
+    // Site
+    BadSiteTable,
+    BadSiteField(&'static str),
+    MissingBaseUrl(&'static str),
+
+    // Feed
+    BadFeedLimit,
+
+    // Sitemap
+    BadSitemapTable,
 }
 
 impl std::fmt::Display for ParseError {
@@ -20,12 +50,57 @@ impl std::fmt::Display for ParseError {
         match self {
             Self::MissingField(field) => write!(f, "Missing field `{field}`"),
             Self::BadArrayItem => write!(f, "Array item is incorrect type"),
+            Self::BadPaginateBy => write!(f, "`paginate_by` must be a positive integer"),
             Self::TomlError(e) => write!(f, "An error occurred when parsing TOML: {e}"),
 
             Self::BadRegex(re) => write!(f, "The regex `{re}` is invalid"),
+            Self::BadGlob(glob, e) => write!(f, "The glob `{glob}` is invalid: {e}"),
+            Self::RuleAndGlob => write!(f, "A rule can have a `rule` or a `glob`, but not both"),
             Self::NoSuchFilter(filter) => write!(f, "The filter `{filter}` does not exist"),
+            Self::BadUnmatched(policy) => write!(
+                f,
+                "`unmatched` must be one of `ignore`, `warn`, `error` or `copy`, not `{policy}`"
+            ),
 
+            Self::BadShell(shell) => write!(
+                f,
+                "`shell` must be `true`, `false` or the path to a shell, not `{shell}`"
+            ),
+            Self::BadTimeout(timeout) => write!(
+                f,
+                "`timeout` must be a positive number of seconds, not `{timeout}`"
+            ),
+            Self::BadLimit(name, limit) => {
+                write!(f, "`{name}` must be a positive whole number, not `{limit}`")
+            }
+            Self::BadEnvTable => write!(f, "`env` must be a table"),
+            Self::BadEnv(key) => write!(f, "`env.{key}` must be a string, number or boolean"),
+            Self::BadCwd => write!(f, "`cwd` must be a path"),
             Self::MissingFilterName => write!(f, "Named filter is missing name"),
+            Self::NoSuchBuiltin(builtin) => write!(f, "The builtin `{builtin}` does not exist"),
+            Self::NoSuchTheme(theme) => write!(
+                f,
+                "The highlighting theme `{theme}` does not exist, valid themes are: {}",
+                Highlight::themes().join(", ")
+            ),
+
+            Self::BuiltinStdio(builtin) => {
+                write!(f, "The builtin `{builtin}` can't be used with `stdio`")
+            }
+
+            Self::DuplicateCollection(name) => {
+                write!(f, "The collection `{name}` is defined more than once")
+            }
+
+            Self::NoSuchCollection(name) => write!(f, "The collection `{name}` does not exist"),
+
+            Self::BadSiteTable => write!(f, "`site` must be a table"),
+            Self::BadSiteField(field) => write!(f, "Site field `{field}` must be a string"),
+            Self::MissingBaseUrl(what) => write!(f, "`site.base_url` must be set to use {what}"),
+
+            Self::BadFeedLimit => write!(f, "Feed `limit` must be a positive integer"),
+
+            Self::BadSitemapTable => write!(f, "`sitemap` must be a table"),
         }
     }
 }
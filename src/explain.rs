@@ -0,0 +1,207 @@
+use std::fs::remove_dir_all;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use log::error;
+
+use crate::build::{self, Options};
+use crate::filepath::FilePath;
+use crate::parse::ParsedDataResult;
+use crate::rule::{self, Unmatched};
+
+// Kept apart from `temp/` so explaining a file never disturbs a build
+const TEMP: &str = "temp/explain";
+
+/// Runs a single content file through its rule, writing each step to `out`:
+/// which rules match, the frontmatter, every filter command and temporary
+/// file, and the output. Nothing is written to the output directory.
+///
+/// Temporary files are deleted afterwards, unless `keep_temp` is set.
+pub fn explain(
+    parsed: &ParsedDataResult,
+    options: &Options,
+    file: &str,
+    keep_temp: bool,
+    out: &mut dyn Write,
+) -> bool {
+    let path = match FilePath::from_str(file.trim_start_matches("./")) {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Invalid file path `{}`: {}", file, e);
+            return false;
+        }
+    };
+
+    if !Path::new(&path.full()).is_file() {
+        error!("`{}` does not exist", path);
+        return false;
+    }
+
+    _ = writeln!(out, "Explaining `{path}`");
+    if !path.full().starts_with(&format!("{}/", options.content)) {
+        _ = writeln!(
+            out,
+            "Note: this file isn't in the content directory (`{}/`), so builds ignore it",
+            options.content
+        );
+    }
+
+    _ = writeln!(out, "Rules:");
+    let matched = rule::matching(&parsed.rules, &path, &options.content);
+    for (i, rule) in parsed.rules.iter().enumerate() {
+        let status = if rule.excludes(&path, &options.content) {
+            "excluded"
+        } else if !rule.matches(&path, &options.content) {
+            "no match"
+        } else if matched.iter().any(|r| std::ptr::eq(*r, rule)) {
+            "matched, used"
+        } else {
+            "matched, but an earlier rule doesn't `continue`"
+        };
+
+        _ = writeln!(out, "    #{} `{}`: {}", i + 1, rule.pattern(), status);
+    }
+
+    if matched.is_empty() {
+        match parsed.unmatched {
+            Unmatched::Ignore | Unmatched::Warn => {
+                _ = writeln!(out, "No rule matches, so this file is skipped")
+            }
+            Unmatched::Error => _ = writeln!(out, "No rule matches, so building this file fails"),
+            Unmatched::Copy => {
+                _ = writeln!(
+                    out,
+                    "No rule matches, so this file is copied to `{}`",
+                    path.clone()
+                        .strip_prefix(&options.content)
+                        .prefix(&options.output)
+                )
+            }
+        }
+
+        return true;
+    }
+
+    // Collections are read from the last build's temporary files, not the
+    // ones in `TEMP`
+    let Some(mut templates) = build::load_templates(&parsed.site) else {
+        return false;
+    };
+
+    let content_files = build::visit_dirs(Path::new(&options.content)).unwrap_or_default();
+    let members = content_files
+        .iter()
+        .filter(|f| {
+            parsed
+                .collections
+                .iter()
+                .any(|c| c.matches(f, &options.content))
+        })
+        .collect::<Vec<_>>();
+    let pages = build::pages(&parsed.rules, &members, &options.content);
+    build::set_collections(
+        &mut templates,
+        &parsed.collections,
+        &pages,
+        &options.content,
+    );
+
+    _ = remove_dir_all(TEMP);
+
+    let output = format!("{TEMP}/{}", options.output);
+    _ = writeln!(
+        out,
+        "Outputs go in `{}/` instead of `{}/`",
+        output, options.output
+    );
+
+    let success = matched.iter().all(|rule| {
+        _ = writeln!(out, "Applying rule `{}`", rule.pattern());
+        rule.exec(
+            path.clone(),
+            &options.content,
+            &output,
+            TEMP,
+            &templates,
+            Some(&mut *out),
+        )
+        .is_some()
+    });
+
+    if keep_temp {
+        _ = writeln!(out, "Kept temporary files in `{TEMP}/`");
+    } else if let Err(e) = remove_dir_all(TEMP) {
+        error!("Failed to remove `{}/`: {}", TEMP, e);
+    }
+
+    success
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::tests::TestSite;
+    use crate::parse;
+
+    #[test]
+    fn traces_each_step() {
+        let site = TestSite::new(
+            "explain",
+            &[
+                (
+                    "rules.toml",
+                    r#"
+                    filters = []
+
+                    [[rules]]
+                    rule = "content/.*"
+                    filters = [{command = "tr a-z A-Z", stdio = true}]
+                    templates = ["page.html"]
+                    output = "{dir}/{name}.html"
+                    "#,
+                ),
+                ("templates/page.html", "<p>{{title}}</p>"),
+                ("content/about.md", "---\ntitle: About\n---\nhello\n"),
+            ],
+        );
+
+        let parsed = parse::parse(site.read("rules.toml"), None).unwrap();
+        let options = Options {
+            content: String::from("content"),
+            output: String::from("output"),
+            public: String::from("public"),
+            force: false,
+            jobs: 1,
+        };
+
+        let mut out = Vec::new();
+        assert!(explain(
+            &parsed,
+            &options,
+            "content/about.md",
+            true,
+            &mut out
+        ));
+        let out = String::from_utf8(out).unwrap();
+
+        for line in [
+            "Explaining `content/about.md`",
+            "    #1 `content/.*`: matched, used",
+            "Frontmatter keys: title",
+            "Filter #1: `tr a-z A-Z`",
+            "    input: in memory, on stdin",
+            "Output: `temp/explain/output/about.html`",
+        ] {
+            assert!(out.lines().any(|l| l == line), "no `{line}` in:\n{out}");
+        }
+
+        let written = out.lines().find_map(|l| l.strip_prefix("Written to `"));
+        assert!(
+            written.is_some_and(|w| w.starts_with("temp/explain/")),
+            "{out}"
+        );
+        assert!(site.exists("temp/explain/output/about.html"));
+        assert!(!site.exists("output"));
+    }
+}
@@ -0,0 +1,352 @@
+use std::fs::{create_dir_all, write};
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset, Utc};
+use log::debug;
+use serde_yaml::Value;
+
+use crate::collection::Collection;
+use crate::filepath::FilePath;
+use crate::helpers::{escape_xml, parse_date, url_join};
+use crate::page::{self, Page};
+use crate::rule::Pattern;
+use crate::site::Site;
+
+/// An RSS and/or Atom feed of every page matching a regex, newest first.
+#[derive(Debug, Clone)]
+pub struct Feed {
+    collection: Collection,
+    rss: Option<String>,
+    atom: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+    limit: Option<usize>,
+}
+
+// A page as it appears in a feed
+struct Item<'a> {
+    title: String,
+    url: String,
+    date: Option<DateTime<FixedOffset>>,
+    summary: Option<&'a str>,
+    content: &'a str,
+}
+
+impl Feed {
+    pub fn new(
+        rule: &str,
+        rss: Option<String>,
+        atom: Option<String>,
+        title: Option<String>,
+        description: Option<String>,
+        limit: Option<usize>,
+    ) -> Result<Self, fancy_regex::Error> {
+        Ok(Self {
+            collection: Collection::new(
+                String::new(),
+                Pattern::regex(rule)?,
+                Some("date".into()),
+                true,
+                None,
+            ),
+            rss,
+            atom,
+            title,
+            description,
+            limit,
+        })
+    }
+
+    /// Writes the feeds into `output`. Returns the paths of the written files.
+    pub fn generate(
+        &self,
+        pages: &[Page],
+        site: &Site,
+        content: &str,
+        output: &str,
+    ) -> Result<Vec<FilePath>, String> {
+        let base_url = site.base_url().unwrap_or_default();
+        let mut members = self.collection.collect(pages, content);
+        if let Some(limit) = self.limit {
+            members.truncate(limit);
+        }
+
+        let items = members
+            .iter()
+            .map(|p| Item {
+                title: meta_str(p, "title")
+                    .map(String::from)
+                    .unwrap_or_else(|| p.source.name()),
+                url: url_join(&[base_url, &p.url()]),
+                date: meta_str(p, "date").and_then(parse_date),
+                summary: meta_str(p, "summary"),
+                content: &p.content,
+            })
+            .collect::<Vec<_>>();
+
+        let mut files = Vec::new();
+        if let Some(path) = &self.rss {
+            let url = url_join(&[base_url, &page::url(&out_path(path)?)]);
+            files.push(write_feed(output, path, self.rss(site, &url, &items))?);
+        }
+
+        if let Some(path) = &self.atom {
+            let url = url_join(&[base_url, &page::url(&out_path(path)?)]);
+            files.push(write_feed(output, path, self.atom(site, &url, &items))?);
+        }
+
+        Ok(files)
+    }
+
+    fn title<'a>(&'a self, site: &'a Site) -> &'a str {
+        self.title
+            .as_deref()
+            .or_else(|| site.get("title"))
+            .unwrap_or_default()
+    }
+
+    fn description<'a>(&'a self, site: &'a Site) -> &'a str {
+        self.description
+            .as_deref()
+            .or_else(|| site.get("description"))
+            .unwrap_or_default()
+    }
+
+    fn rss(&self, site: &Site, url: &str, items: &[Item]) -> String {
+        let mut xml = String::from(concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            "\n",
+            r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:content="http://purl.org/rss/1.0/modules/content/">"#,
+            "\n<channel>\n"
+        ));
+
+        element(&mut xml, "title", self.title(site));
+        element(&mut xml, "link", site.base_url().unwrap_or_default());
+        element(&mut xml, "description", self.description(site));
+        if let Some(language) = site.get("language") {
+            element(&mut xml, "language", language);
+        }
+        if let Some(date) = items.iter().filter_map(|i| i.date).max() {
+            element(&mut xml, "lastBuildDate", &date.to_rfc2822());
+        }
+        xml.push_str(&format!(
+            "<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+            escape_xml(url)
+        ));
+
+        for item in items {
+            xml.push_str("<item>\n");
+            element(&mut xml, "title", &item.title);
+            element(&mut xml, "link", &item.url);
+            xml.push_str(&format!(
+                "<guid isPermaLink=\"true\">{}</guid>\n",
+                escape_xml(&item.url)
+            ));
+            if let Some(date) = item.date {
+                element(&mut xml, "pubDate", &date.to_rfc2822());
+            }
+            element(
+                &mut xml,
+                "description",
+                item.summary.unwrap_or(item.content),
+            );
+            element(&mut xml, "content:encoded", item.content);
+            xml.push_str("</item>\n");
+        }
+
+        xml.push_str("</channel>\n</rss>\n");
+        xml
+    }
+
+    fn atom(&self, site: &Site, url: &str, items: &[Item]) -> String {
+        let base_url = site.base_url().unwrap_or_default();
+        let now = Utc::now().fixed_offset();
+        let mut xml = String::from(concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            "\n",
+            r#"<feed xmlns="http://www.w3.org/2005/Atom">"#,
+            "\n"
+        ));
+
+        element(&mut xml, "title", self.title(site));
+        let description = self.description(site);
+        if !description.is_empty() {
+            element(&mut xml, "subtitle", description);
+        }
+        element(&mut xml, "id", base_url);
+        xml.push_str(&format!(
+            "<link href=\"{}\" rel=\"self\"/>\n<link href=\"{}\"/>\n",
+            escape_xml(url),
+            escape_xml(base_url)
+        ));
+        let updated = items.iter().filter_map(|i| i.date).max().unwrap_or(now);
+        element(&mut xml, "updated", &updated.to_rfc3339());
+        if let Some(author) = site.get("author") {
+            xml.push_str("<author>\n");
+            element(&mut xml, "name", author);
+            xml.push_str("</author>\n");
+        }
+
+        for item in items {
+            xml.push_str("<entry>\n");
+            element(&mut xml, "title", &item.title);
+            xml.push_str(&format!("<link href=\"{}\"/>\n", escape_xml(&item.url)));
+            element(&mut xml, "id", &item.url);
+            element(&mut xml, "updated", &item.date.unwrap_or(now).to_rfc3339());
+            if let Some(summary) = item.summary {
+                element(&mut xml, "summary", summary);
+            }
+            xml.push_str(&format!(
+                "<content type=\"html\">{}</content>\n",
+                escape_xml(item.content)
+            ));
+            xml.push_str("</entry>\n");
+        }
+
+        xml.push_str("</feed>\n");
+        xml
+    }
+}
+
+fn meta_str<'a>(page: &'a Page, key: &str) -> Option<&'a str> {
+    page.meta.get(key).and_then(Value::as_str)
+}
+
+fn out_path(path: &str) -> Result<FilePath, String> {
+    FilePath::from_str(path.trim_start_matches('/'))
+        .map_err(|e| format!("Feed path {path} invalid: {e}"))
+}
+
+fn write_feed(output: &str, path: &str, xml: String) -> Result<FilePath, String> {
+    let out = out_path(path)?.prefix(output);
+    create_dir_all(out.dir()).map_err(|e| format!("Failed to create {}: {e}", out.dir()))?;
+
+    debug!("Writing feed `{}`", out.full());
+    write(out.full(), xml).map_err(|e| format!("Failed to write {}: {e}", out.full()))?;
+
+    Ok(out)
+}
+
+fn element(xml: &mut String, name: &str, text: &str) {
+    xml.push_str(&format!("<{name}>{}</{name}>\n", escape_xml(text)));
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_yaml::Mapping;
+
+    use super::*;
+
+    fn page(name: &str, meta: &str, content: &str) -> Page {
+        Page {
+            source: FilePath::from_str(&format!("content/blog/{name}.md")).unwrap(),
+            output: FilePath::from_str(&format!("blog/{name}/index.html")).unwrap(),
+            meta: serde_yaml::from_str::<Mapping>(meta).unwrap(),
+            content: content.to_string(),
+        }
+    }
+
+    fn site() -> Site {
+        Site::new(
+            toml::from_str(
+                r#"
+                title = "Tom & Jerry's"
+                base_url = "https://example.com/"
+                author = "Tom <tom@example.com>"
+                "#,
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn rss_and_atom() {
+        let pages = [
+            page("old", "title: Old\ndate: 2024-01-02", "<p>old</p>"),
+            page(
+                "fish&chips",
+                "title: Fish & <Chips>\ndate: 2024-03-04T05:06:07+01:00\nsummary: '\"Quoted\"'",
+                "<p>a & b</p>",
+            ),
+            page("undated", "title: Undated", ""),
+        ];
+        let feed = Feed::new(
+            "content/blog/.*",
+            Some("feed.xml".into()),
+            Some("/atom.xml".into()),
+            None,
+            None,
+            Some(2),
+        )
+        .unwrap();
+
+        let out = std::env::temp_dir().join(format!("rssg-feed-{}", std::process::id()));
+        let out = out.display().to_string();
+        let files = feed.generate(&pages, &site(), "content", &out).unwrap();
+        let read = |file: &FilePath| std::fs::read_to_string(file.full()).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            read(&files[0]),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+<channel>
+<title>Tom &amp; Jerry&apos;s</title>
+<link>https://example.com/</link>
+<description></description>
+<lastBuildDate>Mon, 4 Mar 2024 05:06:07 +0100</lastBuildDate>
+<atom:link href="https://example.com/feed.xml" rel="self" type="application/rss+xml"/>
+<item>
+<title>Fish &amp; &lt;Chips&gt;</title>
+<link>https://example.com/blog/fish&amp;chips/</link>
+<guid isPermaLink="true">https://example.com/blog/fish&amp;chips/</guid>
+<pubDate>Mon, 4 Mar 2024 05:06:07 +0100</pubDate>
+<description>&quot;Quoted&quot;</description>
+<content:encoded>&lt;p&gt;a &amp; b&lt;/p&gt;</content:encoded>
+</item>
+<item>
+<title>Old</title>
+<link>https://example.com/blog/old/</link>
+<guid isPermaLink="true">https://example.com/blog/old/</guid>
+<pubDate>Tue, 2 Jan 2024 00:00:00 +0000</pubDate>
+<description>&lt;p&gt;old&lt;/p&gt;</description>
+<content:encoded>&lt;p&gt;old&lt;/p&gt;</content:encoded>
+</item>
+</channel>
+</rss>
+"#
+        );
+        assert_eq!(
+            read(&files[1]),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>Tom &amp; Jerry&apos;s</title>
+<id>https://example.com/</id>
+<link href="https://example.com/atom.xml" rel="self"/>
+<link href="https://example.com/"/>
+<updated>2024-03-04T05:06:07+01:00</updated>
+<author>
+<name>Tom &lt;tom@example.com&gt;</name>
+</author>
+<entry>
+<title>Fish &amp; &lt;Chips&gt;</title>
+<link href="https://example.com/blog/fish&amp;chips/"/>
+<id>https://example.com/blog/fish&amp;chips/</id>
+<updated>2024-03-04T05:06:07+01:00</updated>
+<summary>&quot;Quoted&quot;</summary>
+<content type="html">&lt;p&gt;a &amp; b&lt;/p&gt;</content>
+</entry>
+<entry>
+<title>Old</title>
+<link href="https://example.com/blog/old/"/>
+<id>https://example.com/blog/old/</id>
+<updated>2024-01-02T00:00:00+00:00</updated>
+<content type="html">&lt;p&gt;old&lt;/p&gt;</content>
+</entry>
+</feed>
+"#
+        );
+
+        _ = std::fs::remove_dir_all(&out);
+    }
+}
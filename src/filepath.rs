@@ -48,7 +48,7 @@ impl FilePath {
 
     pub fn prefix<S: ToString>(mut self, pre: S) -> Self {
         let pre = pre.to_string();
-        if let Some(path) = self.dir {
+        if let Some(path) = self.dir.filter(|d| !d.is_empty()) {
             self.dir = Some(format!(
                 "{}/{}",
                 pre.strip_suffix('/').map(String::from).unwrap_or(pre),
@@ -80,7 +80,7 @@ impl FilePath {
     pub fn parent(&self) -> String {
         Path::new(&self.dir())
             .components()
-            .last()
+            .next_back()
             .map(|s| s.as_os_str().to_string_lossy().to_string())
             .unwrap_or_default()
     }
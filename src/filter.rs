@@ -3,7 +3,9 @@ use std::str::FromStr;
 
 use log::{debug, error};
 use serde::Deserialize;
+use serde_yaml::Mapping;
 
+use crate::builtin::Builtin;
 use crate::command::{Command, ExitStatus};
 use crate::filepath::FilePath;
 use crate::tempfile::tempdir;
@@ -17,27 +19,72 @@ pub fn substitute(string: &str, path: &FilePath) -> String {
         .replace("{parent}", &path.parent())
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub enum FilterKind {
+    Command(Command),
+    Builtin(Builtin),
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Filter {
-    command: Command,
+    kind: FilterKind,
     outfile: Option<String>,
     pub give_original: bool,
+    /// Reads the file from stdin and writes the result to stdout, instead of
+    /// using paths.
+    pub stdio: bool,
 }
 
 impl Filter {
-    pub fn new(command: String, outfile: Option<String>, give_original: bool) -> Self {
+    pub fn new(
+        command: Command,
+        outfile: Option<String>,
+        give_original: bool,
+        stdio: bool,
+    ) -> Self {
         Self {
-            command: Command::new(command),
+            kind: FilterKind::Command(command),
             outfile,
             give_original,
+            stdio,
+        }
+    }
+
+    /// A filter that runs a builtin instead of a command. Builtins always
+    /// have an outfile.
+    pub fn builtin(builtin: Builtin, outfile: String) -> Self {
+        Self {
+            kind: FilterKind::Builtin(builtin),
+            outfile: Some(outfile),
+            give_original: false,
+            stdio: false,
+        }
+    }
+
+    /// The command this filter runs, or the name of the builtin.
+    pub fn str(&self) -> String {
+        match &self.kind {
+            FilterKind::Command(command) => command.str().to_string(),
+            FilterKind::Builtin(builtin) => format!("builtin:{}", builtin.name()),
+        }
+    }
+
+    pub fn as_builtin(&self) -> Option<&Builtin> {
+        match &self.kind {
+            FilterKind::Builtin(builtin) => Some(builtin),
+            FilterKind::Command(_) => None,
         }
     }
 
-    pub fn tempdir(&self, path: &FilePath) -> Result<FilePath, String> {
+    /// Where the filter's outfile for `path` goes, under the temporary
+    /// directory `temp`. The directory depends on `path` too, so that files
+    /// built in parallel never share an outfile, even if it doesn't use `{dir}`
+    /// or `{name}`.
+    pub fn tempdir(&self, temp: &str, path: &FilePath) -> Result<FilePath, String> {
         match FilePath::from_str(
             &substitute(self.outfile.as_ref().unwrap(), path).replace("{\\{", "{{"),
         ) {
-            Ok(new) => Ok(tempdir(self.command.str(), &new)),
+            Ok(new) => Ok(tempdir(temp, &format!("{} {path}", self.str()), &new)),
 
             Err(e) => Err(format!(
                 "Filter outfile {} invalid: {e}",
@@ -46,18 +93,51 @@ impl Filter {
         }
     }
 
+    /// Where the output of a `stdio` filter goes, if it has to be written to
+    /// a file. Named after `outfile` if there is one.
+    pub fn stdio_path(&self, temp: &str, path: &FilePath) -> Result<FilePath, String> {
+        if self.has_outfile() {
+            self.tempdir(temp, path)
+        } else {
+            Ok(tempdir(temp, &self.str(), path))
+        }
+    }
+
+    /// What running the filter on `path` would do, i.e. the command after
+    /// substitutions.
+    pub fn describe(&self, temp: &str, path: &FilePath, meta: Option<&Mapping>) -> String {
+        match &self.kind {
+            FilterKind::Command(command) => {
+                let out = if self.has_outfile() && !self.stdio {
+                    self.tempdir(temp, path).ok()
+                } else {
+                    None
+                };
+                command.substituted(Some(path), out.as_ref(), meta)
+            }
+            FilterKind::Builtin(_) => self.str(),
+        }
+    }
+
     pub fn has_outfile(&self) -> bool {
         self.outfile.is_some()
     }
 
     // Execute the filter.
     //
-    // Logs directly to stdout/stderr. Returns true on a successful run.
-    // Returns the frontmatter, if any.
-    pub fn exec(&self, path: &FilePath) -> bool {
+    // Logs directly to stdout/stderr, and to `log` if the command asks to.
+    // Returns true on a successful run.
+    pub fn exec(
+        &self,
+        temp: &str,
+        path: &FilePath,
+        env: &[(String, String)],
+        meta: Option<&Mapping>,
+        log: &str,
+    ) -> bool {
         // If outfile is an invalid path, then don't bother running the filter
         let out = if self.has_outfile() {
-            let out = match self.tempdir(path) {
+            let out = match self.tempdir(temp, path) {
                 Ok(new) => new,
 
                 Err(e) => {
@@ -79,28 +159,104 @@ impl Filter {
             None
         };
 
-        debug!("Running filter `{}`", self.command.str());
-        match self.command.exec(Some(path), out) {
-            ExitStatus::Success(cmd) => {
-                debug!("Filter `{}` exited successfully", cmd);
-                true
+        debug!("Running filter `{}`", self.str());
+        let command = match &self.kind {
+            FilterKind::Command(command) => command,
+            FilterKind::Builtin(builtin) => {
+                // Builtins always have an outfile
+                let out = out.unwrap();
+                return match builtin.exec(path, &out) {
+                    Ok(()) => {
+                        debug!("Builtin `{}` finished successfully", builtin.name());
+                        true
+                    }
+                    Err(e) => {
+                        error!("Builtin `{}` failed: {}", builtin.name(), e);
+                        false
+                    }
+                };
             }
+        };
 
-            ExitStatus::InvalidCommand(cmd) => {
-                error!("Filter `{}` failed: not a valid command", cmd);
-                false
-            }
-            ExitStatus::NonZero(cmd, code) => {
-                error!(
-                    "Filter `{}` failed: exited with non-zero code {}",
-                    cmd, code
-                );
-                false
-            }
-            ExitStatus::Failed(cmd, e) => {
-                error!("Filter `{}` failed: {}", cmd, e);
-                false
-            }
+        report(command.exec(Some(path), out, env, meta, log))
+    }
+
+    /// Runs a `stdio` filter, with `input` as the file. `path` is only used
+    /// for substitutions. Returns the new file, or `None` if it failed.
+    pub fn pipe(
+        &self,
+        path: &FilePath,
+        input: Vec<u8>,
+        env: &[(String, String)],
+        meta: Option<&Mapping>,
+        log: &str,
+    ) -> Option<Vec<u8>> {
+        let FilterKind::Command(command) = &self.kind else {
+            error!("Builtin `{}` can't be used with `stdio`", self.str());
+            return None;
+        };
+
+        debug!("Running filter `{}`", self.str());
+        let (status, output) = command.pipe(Some(path), input, env, meta, log);
+        report(status).then_some(output)
+    }
+}
+
+// Logs how a filter's command went, returning true if it succeeded
+fn report(status: ExitStatus) -> bool {
+    match status {
+        ExitStatus::Success(cmd, captured) => {
+            debug!("Filter `{}` exited successfully{}", cmd, captured);
+            true
+        }
+
+        ExitStatus::InvalidCommand(cmd) => {
+            error!("Filter `{}` failed: not a valid command", cmd);
+            false
+        }
+        ExitStatus::NonZero(cmd, code, captured) => {
+            error!(
+                "Filter `{}` failed: exited with non-zero code {}{}",
+                cmd, code, captured
+            );
+            false
+        }
+        ExitStatus::TimedOut(cmd, timeout, captured) => {
+            error!(
+                "Filter `{}` failed: timed out after {}s{}",
+                cmd,
+                timeout.as_secs_f64(),
+                captured
+            );
+            false
+        }
+        ExitStatus::Failed(cmd, e) => {
+            error!("Filter `{}` failed: {}", cmd, e);
+            false
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outfiles_are_unique_per_file() {
+        let filter = Filter::new(
+            Command::new(String::from("pandoc {full} -o {outfile}")),
+            Some(String::from("out.html")),
+            false,
+            false,
+        );
+        let a = FilePath::from_str("temp/1/a.md").unwrap();
+        let b = FilePath::from_str("temp/2/b.md").unwrap();
+
+        let out = |path| filter.tempdir("temp", path).unwrap();
+        let (a_out, b_out) = (out(&a), out(&b));
+        assert_ne!(a_out, b_out);
+        assert_eq!(a_out.name(), "out");
+        assert_eq!(a_out.ext(), "html");
+        assert_eq!(a_out, out(&a));
+    }
+}
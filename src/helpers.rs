@@ -0,0 +1,221 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use handlebars::{handlebars_helper, Handlebars};
+use serde_json::Value;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const DEFAULT_ELLIPSIS: &str = "…";
+
+/// Registers all the built-in template helpers.
+pub fn register(registry: &mut Handlebars) {
+    registry.register_helper("format_date", Box::new(format_date_helper));
+    registry.register_helper("slugify", Box::new(slugify_helper));
+    registry.register_helper("truncate", Box::new(truncate_helper));
+    registry.register_helper("url_join", Box::new(url_join_helper));
+}
+
+/// Parses a date in one of the common formats used in frontmatter: RFC 3339,
+/// RFC 2822, `YYYY-MM-DD HH:MM[:SS]`, or `YYYY-MM-DD`. Dates without a
+/// timezone are assumed to be UTC.
+pub fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    let date = date.trim();
+    if let Ok(d) = DateTime::parse_from_rfc3339(date) {
+        return Some(d);
+    }
+
+    if let Ok(d) = DateTime::parse_from_rfc2822(date) {
+        return Some(d);
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(date, f).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        })?;
+
+    Some(Utc.from_utc_datetime(&naive).fixed_offset())
+}
+
+/// Reformats a date using a `strftime`-style format string. Returns `None` if
+/// the date can't be parsed, or the format is invalid.
+pub fn format_date(date: &str, format: &str) -> Option<String> {
+    let date = parse_date(date)?;
+
+    let mut out = String::new();
+    write!(out, "{}", date.format(format)).ok()?;
+
+    Some(out)
+}
+
+/// Turns a string into a lowercase, URL-safe identifier, replacing every run
+/// of non-alphanumeric characters with a single dash.
+///
+/// `"Hello, World!"` becomes `"hello-world"`.
+pub fn slugify(s: &str) -> String {
+    let mut slug = String::with_capacity(s.len());
+    for ch in s.chars() {
+        if ch.is_alphanumeric() {
+            slug.extend(ch.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    if slug.ends_with('-') {
+        slug.pop();
+    }
+
+    slug
+}
+
+/// Shortens `s` to at most `len` characters, appending `ellipsis` if anything
+/// was cut off.
+pub fn truncate(s: &str, len: usize, ellipsis: &str) -> String {
+    match s.char_indices().nth(len) {
+        Some((i, _)) => format!("{}{ellipsis}", s[..i].trim_end()),
+        None => s.to_string(),
+    }
+}
+
+/// Joins URL segments with exactly one slash between each.
+///
+/// `["https://example.com/", "/blog/", "post"]` becomes
+/// `"https://example.com/blog/post"`.
+pub fn url_join<S: AsRef<str>>(parts: &[S]) -> String {
+    let mut url = String::new();
+    for part in parts.iter().map(AsRef::as_ref).filter(|p| !p.is_empty()) {
+        if url.is_empty() {
+            url.push_str(part);
+        } else {
+            if !url.ends_with('/') {
+                url.push('/');
+            }
+
+            url.push_str(part.trim_start_matches('/'));
+        }
+    }
+
+    url
+}
+
+/// Escapes the characters with special meaning in XML text and attributes.
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn as_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+// {{format_date date "%B %e, %Y"}}
+handlebars_helper!(format_date_helper: |*args| {
+    let date = args.first().map(|v| as_string(v)).unwrap_or_default();
+    let format = args.get(1).and_then(|v| v.as_str()).unwrap_or(DEFAULT_DATE_FORMAT);
+
+    format_date(&date, format).unwrap_or(date)
+});
+
+// {{slugify title}}
+handlebars_helper!(slugify_helper: |s: Json| slugify(&as_string(s)));
+
+// {{truncate summary 100}} or {{truncate summary 100 "..."}}
+handlebars_helper!(truncate_helper: |*args| {
+    let s = args.first().map(|v| as_string(v)).unwrap_or_default();
+    let len = args.get(1).and_then(|v| v.as_u64()).unwrap_or(u64::MAX);
+    let ellipsis = args.get(2).and_then(|v| v.as_str()).unwrap_or(DEFAULT_ELLIPSIS);
+
+    truncate(&s, len.try_into().unwrap_or(usize::MAX), ellipsis)
+});
+
+// {{url_join site.base_url "blog" slug}}
+handlebars_helper!(url_join_helper: |*args| {
+    url_join(&args.iter().map(|v| as_string(v)).collect::<Vec<_>>())
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        let date = |d| parse_date(d).map(|d| d.to_rfc3339());
+
+        assert_eq!(
+            date("2024-03-01T12:30:00+02:00").as_deref(),
+            Some("2024-03-01T12:30:00+02:00")
+        );
+        assert_eq!(
+            date("Fri, 01 Mar 2024 12:30:00 +0000").as_deref(),
+            Some("2024-03-01T12:30:00+00:00")
+        );
+        assert_eq!(
+            date("2024-03-01 12:30").as_deref(),
+            Some("2024-03-01T12:30:00+00:00")
+        );
+        assert_eq!(
+            date(" 2024-03-01 ").as_deref(),
+            Some("2024-03-01T00:00:00+00:00")
+        );
+        assert_eq!(date("March 1st"), None);
+        assert_eq!(date("2024-13-01"), None);
+
+        assert_eq!(
+            format_date("2024-03-01", "%B %e, %Y").as_deref(),
+            Some("March  1, 2024")
+        );
+        assert_eq!(format_date("2024-03-01", "%Q"), None);
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  --Rust & C++--  "), "rust-c");
+        assert_eq!(slugify("Ünïcode Straße"), "ünïcode-straße");
+        assert_eq!(slugify("!!!"), "");
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate("short", 10, "…"), "short");
+        assert_eq!(truncate("exactly", 7, "…"), "exactly");
+        assert_eq!(truncate("Hello world", 6, "..."), "Hello...");
+        assert_eq!(truncate("héllo", 2, "…"), "hé…");
+        assert_eq!(truncate("anything", 0, "…"), "…");
+    }
+
+    #[test]
+    fn url_joining() {
+        assert_eq!(
+            url_join(&["https://example.com/", "/blog/", "post"]),
+            "https://example.com/blog/post"
+        );
+        assert_eq!(
+            url_join(&["https://example.com", "", "post/"]),
+            "https://example.com/post/"
+        );
+        assert_eq!(url_join(&["/", "blog"]), "/blog");
+        assert_eq!(url_join::<&str>(&[]), "");
+    }
+
+    #[test]
+    fn xml_escaping() {
+        assert_eq!(
+            escape_xml(r#"<a href="/?x=1&y='2'">"#),
+            "&lt;a href=&quot;/?x=1&amp;y=&apos;2&apos;&quot;&gt;"
+        );
+        assert_eq!(escape_xml("&amp;"), "&amp;amp;");
+        assert_eq!(escape_xml("plain text"), "plain text");
+    }
+}
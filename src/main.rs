@@ -1,18 +1,32 @@
 mod build;
+mod builtin;
 mod cache;
+mod collection;
 mod command;
+mod data;
 mod error;
+mod explain;
+mod feed;
 mod filepath;
 mod filter;
+mod helpers;
+mod page;
+mod paginate;
 mod parse;
 mod rule;
+mod serve;
+mod site;
+mod sitemap;
+mod taxonomy;
 mod tempfile;
 mod template;
+mod watch;
 
 use std::fs::{self, File};
-use std::io::ErrorKind;
+use std::io::{self, ErrorKind};
 use std::path::Path;
 use std::process::exit;
+use std::thread;
 
 use log::{error, info};
 use sarge::prelude::*;
@@ -20,10 +34,16 @@ use sarge::prelude::*;
 struct Args {
     help: bool,
     compile: bool,
+    watch: bool,
+    serve: Option<String>,
     init: Option<String>,
     logfile: Option<String>,
+    base_url: Option<String>,
     verbose: bool,
     force: bool,
+    jobs: usize,
+    explain: Option<String>,
+    keep_temp: bool,
 
     content: Option<String>,
     output: Option<String>,
@@ -36,17 +56,30 @@ fn main() {
     let args = {
         let help = parser.add(tag::both('h', "help"));
         let compile = parser.add(tag::both('c', "compile"));
+        let watch = parser.add(tag::both('w', "watch"));
+        let serve = parser.add(tag::long("serve"));
         let init = parser.add(tag::both('i', "init"));
         let logfile = parser.add(tag::both('l', "logfile").env("RSSG_LOGFILE"));
+        let base_url = parser.add(tag::long("base-url").env("RSSG_BASE_URL"));
         let verbose = parser.add(tag::both('v', "verbose"));
         let force = parser.add(tag::both('f', "force"));
+        let jobs = parser.add::<u64>(tag::both('j', "jobs"));
+        let keep_temp = parser.add(tag::both('k', "keep-temp"));
 
         let content = parser.add(tag::long("content"));
         let output = parser.add(tag::long("output"));
         let public = parser.add(tag::long("public"));
         let clean = parser.add(tag::long("clean"));
 
-        let _args = match parser.parse() {
+        // `--serve` takes an optional address, fill in the default if omitted
+        let mut cli = std::env::args().collect::<Vec<_>>();
+        if let Some(i) = cli.iter().position(|a| a == "--serve") {
+            if cli.get(i + 1).is_none_or(|a| a.starts_with('-')) {
+                cli.insert(i + 1, String::from(serve::DEFAULT_ADDR));
+            }
+        }
+
+        let positional = match parser.parse_provided(&cli, std::env::vars()) {
             Ok(a) => a,
             Err(e) => {
                 eprintln!("ERROR: Failed to parse arguments: {e}");
@@ -57,10 +90,27 @@ fn main() {
         Args {
             help: help.get().unwrap(),
             compile: compile.get().unwrap(),
+            watch: watch.get().unwrap(),
+            serve: serve.get().ok(),
             init: init.get().ok(),
             logfile: logfile.get().ok(),
+            base_url: base_url.get().ok(),
             verbose: verbose.get().unwrap(),
             force: force.get().unwrap(),
+            jobs: match jobs.get() {
+                Ok(0) => thread::available_parallelism().map_or(1, |n| n.get()),
+                Ok(n) => n as usize,
+                Err(_) => 1,
+            },
+            explain: match positional.as_slice() {
+                [command, file] if command == "explain" => Some(file.clone()),
+                [command, ..] if command == "explain" => {
+                    eprintln!("ERROR: Usage: explain <file>");
+                    exit(1);
+                }
+                _ => None,
+            },
+            keep_temp: keep_temp.get().unwrap(),
             content: content.get().ok(),
             output: output.get().ok(),
             public: public.get().ok(),
@@ -69,22 +119,36 @@ fn main() {
     };
 
     if args.help {
-        println!(
-            "{} [options]",
-            parser.binary().unwrap_or_else(|| String::from("rssg"))
-        );
+        let binary = parser.binary().unwrap_or_else(|| String::from("rssg"));
+        println!("{binary} [options]");
+        println!("{binary} [options] explain <file>");
         println!("     -h |    --help : print this help dialog");
         println!("     -c | --compile : compile the site");
+        println!("     -w |   --watch : compile the site, then");
+        println!("                      recompile on changes");
+        println!("     --serve [addr] : watch, and serve the site");
+        println!("                      with live reload");
+        println!(
+            "                      defaults to `{}`",
+            serve::DEFAULT_ADDR
+        );
         println!("     -i |    --init : create a new site");
         println!("     -v | --verbose : include debug output");
         println!("     -f |   --force : force recompilation");
         println!("                      rebuilds cache");
+        println!("     -j |    --jobs : build N files at once");
+        println!("                      0 uses every core");
+        println!("     explain <file> : trace a content file");
+        println!("                      through its rule");
         println!("          --content : set source directory");
         println!("                      defaults to `content`");
         println!("           --output : set output directory");
         println!("                      defaults to `output`");
         println!("           --public : set public directory");
         println!("                      defaults to `public`");
+        println!("         --base-url : override `site.base_url`");
+        println!("   -k | --keep-temp : keep temporary files");
+        println!("                      from `explain`");
         println!("            --clean : cleans the `output`");
         println!("                      and `temp` directories");
 
@@ -92,12 +156,22 @@ fn main() {
     }
 
     let mut dis = fern::Dispatch::new().format(|out, message, record| {
-        out.finish(format_args!(
-            "[{} {}] {}",
-            record.level(),
-            record.target(),
-            message
-        ))
+        if let Some(file) = build::current_file() {
+            out.finish(format_args!(
+                "[{} {}] `{}`: {}",
+                record.level(),
+                record.target(),
+                file,
+                message
+            ))
+        } else {
+            out.finish(format_args!(
+                "[{} {}] {}",
+                record.level(),
+                record.target(),
+                message
+            ))
+        }
     });
 
     if args.verbose {
@@ -176,52 +250,128 @@ fn main() {
         }
     }
 
-    if args.compile {
+    let content = args.content.unwrap_or_else(|| String::from("content"));
+
+    let public = args.public.unwrap_or_else(|| String::from("public"));
+
+    if let Some(file) = &args.explain {
         if !Path::new("rules.toml").exists() {
             error!("No `rules.toml` found, aborting");
             exit(1);
         }
 
-        let content = args.content.unwrap_or_else(|| String::from("content"));
+        let Some(parsed) = read_rules(args.base_url.as_deref()) else {
+            exit(1);
+        };
 
-        let public = args.public.unwrap_or_else(|| String::from("public"));
+        let options = build::Options {
+            content,
+            output,
+            public,
+            force: false,
+            jobs: 1,
+        };
+
+        if !explain::explain(&parsed, &options, file, args.keep_temp, &mut io::stdout()) {
+            exit(1);
+        }
+
+        return;
+    }
+
+    let watching = args.watch || args.serve.is_some();
+    if args.compile || watching {
+        if !Path::new("rules.toml").exists() {
+            error!("No `rules.toml` found, aborting");
+            exit(1);
+        }
 
         if !Path::new(&content).exists() {
             error!("Content directory (`{}`) not found, aborting", content);
             exit(1);
         }
 
-        let data = match fs::read_to_string("rules.toml") {
-            Ok(s) => s,
-            Err(e) => {
-                error!("Failed to read `rules.toml`: {}", e);
-                exit(1);
-            }
-        };
-
-        let parse::ParsedDataResult {
-            rules,
-            pre_commands,
-            post_commands,
-        } = match parse::parse(data) {
-            Ok(r) => r,
-            Err(e) => {
-                error!("Failed to parse rules: {}", e);
-                exit(1);
-            }
+        let Some(mut parsed) = read_rules(args.base_url.as_deref()) else {
+            exit(1);
         };
 
-        if !build::build(
-            &rules,
-            pre_commands,
-            post_commands,
+        let options = build::Options {
             content,
             output,
             public,
-            args.force,
-        ) {
+            force: args.force,
+            jobs: args.jobs,
+        };
+
+        let reloader = serve::Reloader::new();
+        if let Some(addr) = &args.serve {
+            if let Err(e) = serve::serve(addr, options.output.clone(), reloader.clone()) {
+                error!("Failed to serve on `{}`: {}", addr, e);
+                exit(1);
+            }
+        }
+
+        if !build::build(&parsed, &options) {
             error!("Build failed");
-            exit(1);
+            if !watching {
+                exit(1);
+            }
+        }
+
+        if watching {
+            info!("Watching for changes, press Ctrl-C to stop");
+            watch::watch(
+                &[
+                    &options.content,
+                    &options.public,
+                    "templates",
+                    "data",
+                    "rules.toml",
+                ],
+                |changed| {
+                    // The cache notices which files the changed rules affect
+                    if changed.iter().any(|f| f.full() == "rules.toml") {
+                        info!("`rules.toml` changed, reparsing");
+                        match read_rules(args.base_url.as_deref()) {
+                            Some(p) => parsed = p,
+                            None => {
+                                error!("Keeping previous rules until `rules.toml` is fixed");
+                                return;
+                            }
+                        }
+                    }
+
+                    // Only the first build is forced
+                    let options = build::Options {
+                        force: false,
+                        ..options.clone()
+                    };
+
+                    if !build::build(&parsed, &options) {
+                        error!("Build failed");
+                    }
+
+                    reloader.reload();
+                },
+            );
+        }
+    }
+}
+
+fn read_rules(base_url: Option<&str>) -> Option<parse::ParsedDataResult> {
+    let data = match fs::read_to_string("rules.toml") {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to read `rules.toml`: {}", e);
+            return None;
+        }
+    };
+
+    match parse::parse(data, base_url) {
+        Ok(r) => Some(r),
+        Err(e) => {
+            error!("Failed to parse rules: {}", e);
+            None
         }
     }
 }
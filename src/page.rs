@@ -0,0 +1,47 @@
+use serde_yaml::{Mapping, Value};
+
+use crate::filepath::FilePath;
+
+/// Turns a path relative to the output directory into a URL relative to the
+/// site root, leaving off `index.html`.
+pub fn url(output: &FilePath) -> String {
+    let full = output.full();
+    let url = full.strip_suffix("index.html").unwrap_or(&full);
+
+    format!("/{}", url.trim_start_matches('/'))
+}
+
+/// A built content file, as seen by listing pages.
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub source: FilePath,
+    /// The output path, relative to the output directory.
+    pub output: FilePath,
+    pub meta: Mapping,
+    /// The content after filters, but before templates.
+    pub content: String,
+}
+
+impl Page {
+    /// The public URL of the page, relative to the site root. `index.html` is
+    /// left off, so `blog/post/index.html` becomes `/blog/post/`.
+    pub fn url(&self) -> String {
+        url(&self.output)
+    }
+
+    /// The collection this page is a listing of, from its `paginate` key.
+    pub fn paginates(&self) -> Option<&str> {
+        self.meta.get("paginate").and_then(Value::as_str)
+    }
+
+    /// The page as template data: its frontmatter, plus `url`, `content` and
+    /// `source`.
+    pub fn to_value(&self) -> Value {
+        let mut data = self.meta.clone();
+        data.insert("url".into(), self.url().into());
+        data.insert("content".into(), self.content.clone().into());
+        data.insert("source".into(), self.source.full().into());
+
+        Value::Mapping(data)
+    }
+}
@@ -0,0 +1,142 @@
+use std::str::FromStr;
+
+use serde_yaml::{Mapping, Value};
+
+use crate::filepath::FilePath;
+use crate::filter::substitute;
+use crate::page;
+
+pub const DEFAULT_PATH: &str = "{dir}/page/{n}/index.html";
+
+/// How to split a listing into several pages.
+#[derive(Debug, Clone)]
+pub struct Paginate {
+    by: usize,
+    path: String,
+}
+
+impl Paginate {
+    pub fn new(by: usize, path: Option<String>) -> Self {
+        Self {
+            by: by.max(1),
+            path: path.unwrap_or_else(|| String::from(DEFAULT_PATH)),
+        }
+    }
+
+    /// Splits `items` into pages. The first page goes to `first`, the rest to
+    /// the pagination path (substituted against `first`, with `{n}` as the
+    /// page number). All paths are relative to the output directory.
+    ///
+    /// Returns the output path of each page, along with its `paginator`
+    /// template variable. There is always at least one page, even if there are
+    /// no items.
+    pub fn paginate(
+        &self,
+        first: &FilePath,
+        items: &[Value],
+    ) -> Result<Vec<(FilePath, Value)>, String> {
+        let chunks = if items.is_empty() {
+            vec![items]
+        } else {
+            items.chunks(self.by).collect()
+        };
+
+        let mut outputs = vec![first.clone()];
+        for n in 2..=chunks.len() {
+            let out = substitute(&self.path, first).replace("{n}", &n.to_string());
+            outputs.push(
+                FilePath::from_str(out.trim_start_matches('/'))
+                    .map_err(|e| format!("Pagination path {out} invalid: {e}"))?,
+            );
+        }
+
+        let urls = outputs.iter().map(page::url).collect::<Vec<_>>();
+        let url = |i: Option<usize>| {
+            i.and_then(|i| urls.get(i))
+                .map_or(Value::Null, |u| u.clone().into())
+        };
+
+        let mut pages = Vec::with_capacity(chunks.len());
+        for (i, (chunk, out)) in chunks.iter().zip(&outputs).enumerate() {
+            let mut paginator = Mapping::new();
+            paginator.insert("pages".into(), Value::Sequence(chunk.to_vec()));
+            paginator.insert("number".into(), (i + 1).into());
+            paginator.insert("total".into(), chunks.len().into());
+            paginator.insert("total_items".into(), items.len().into());
+            paginator.insert("url".into(), url(Some(i)));
+            paginator.insert("prev".into(), url(i.checked_sub(1)));
+            paginator.insert("next".into(), url(Some(i + 1)));
+            paginator.insert("first".into(), url(Some(0)));
+            paginator.insert("last".into(), url(Some(chunks.len() - 1)));
+
+            pages.push((out.clone(), Value::Mapping(paginator)));
+        }
+
+        Ok(pages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paginate(by: usize, path: Option<&str>, items: usize) -> Vec<(String, Value)> {
+        let items = (1..=items).map(Value::from).collect::<Vec<_>>();
+        let first = FilePath::from_str("blog/index.html").unwrap();
+
+        Paginate::new(by, path.map(String::from))
+            .paginate(&first, &items)
+            .unwrap()
+            .into_iter()
+            .map(|(out, paginator)| (out.full(), paginator))
+            .collect()
+    }
+
+    #[test]
+    fn pages_and_paginators() {
+        let pages = paginate(2, None, 5);
+        let outputs = pages.iter().map(|(o, _)| o.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            outputs,
+            [
+                "blog/index.html",
+                "blog/page/2/index.html",
+                "blog/page/3/index.html"
+            ]
+        );
+
+        let paginator = &pages[1].1;
+        assert_eq!(paginator["pages"], Value::from(vec![3, 4]));
+        assert_eq!(paginator["number"], Value::from(2));
+        assert_eq!(paginator["total"], Value::from(3));
+        assert_eq!(paginator["total_items"], Value::from(5));
+        assert_eq!(paginator["url"], Value::from("/blog/page/2/"));
+        assert_eq!(paginator["prev"], Value::from("/blog/"));
+        assert_eq!(paginator["next"], Value::from("/blog/page/3/"));
+        assert_eq!(paginator["first"], Value::from("/blog/"));
+        assert_eq!(paginator["last"], Value::from("/blog/page/3/"));
+
+        assert_eq!(pages[0].1["prev"], Value::Null);
+        assert_eq!(pages[2].1["pages"], Value::from(vec![5]));
+        assert_eq!(pages[2].1["next"], Value::Null);
+    }
+
+    #[test]
+    fn empty_listing_has_one_page() {
+        let pages = paginate(2, None, 0);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].0, "blog/index.html");
+        assert_eq!(pages[0].1["pages"], Value::Sequence(Vec::new()));
+        assert_eq!(pages[0].1["total"], Value::from(1));
+    }
+
+    #[test]
+    fn custom_path() {
+        let pages = paginate(1, Some("/{dir}/{n}.html"), 2);
+        assert_eq!(pages[1].0, "blog/2.html");
+        assert_eq!(pages[1].1["url"], Value::from("/blog/2.html"));
+
+        // Zero is treated as one item per page
+        assert_eq!(paginate(0, None, 2).len(), 2);
+    }
+}
@@ -0,0 +1,50 @@
+use crate::collection::Collection;
+use crate::error::*;
+use crate::field;
+use crate::parse::common::parse_paginate;
+use crate::rule::Pattern;
+
+pub fn parse_collection(data: &toml::Table) -> ParseResult<Collection> {
+    let name = field!(data, name, String);
+    let sort = if let Some(toml::Value::String(s)) = &data.get("sort") {
+        Some(s.clone())
+    } else {
+        None
+    };
+
+    let reverse = matches!(&data.get("reverse"), Some(toml::Value::Boolean(true)));
+    let paginate = parse_paginate(data)?;
+
+    let rule = match (data.get("rule"), data.get("glob")) {
+        (Some(toml::Value::String(rule)), None) => {
+            Pattern::regex(rule).map_err(|_| ParseError::BadRegex(rule.clone()))?
+        }
+        (None, Some(toml::Value::String(glob))) => {
+            Pattern::glob(glob).map_err(|e| ParseError::BadGlob(glob.clone(), e))?
+        }
+        (Some(_), Some(_)) => return Err(ParseError::RuleAndGlob),
+        _ => return Err(ParseError::MissingField("rule")),
+    };
+
+    Ok(Collection::new(name.clone(), rule, sort, reverse, paginate))
+}
+
+pub fn parse_collections(collections: &Vec<toml::Value>) -> ParseResult<Vec<Collection>> {
+    let mut new: Vec<Collection> = Vec::new();
+    for i in collections {
+        if let toml::Value::Table(v) = i {
+            let collection = parse_collection(v)?;
+            if new.iter().any(|c| c.name() == collection.name()) {
+                return Err(ParseError::DuplicateCollection(
+                    collection.name().to_string(),
+                ));
+            }
+
+            new.push(collection);
+        } else {
+            return Err(ParseError::BadArrayItem);
+        }
+    }
+
+    Ok(new)
+}
@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use crate::command::Command;
+use crate::error::*;
+use crate::field;
+use crate::site::Site;
+
+// Used when a command asks for a shell without naming one
+const DEFAULT_SHELL: &str = "/bin/sh";
+
+// The largest `memory_limit`, in megabytes, that still fits in bytes
+const MAX_MEMORY_LIMIT: u64 = u64::MAX / (1024 * 1024);
+
+/// Settings applying to every command, from the top level of `rules.toml`.
+#[derive(Debug, Clone, Default)]
+pub struct CommandDefaults {
+    /// Run every command through this shell, unless it says otherwise.
+    pub shell: Option<String>,
+    /// Log what commands run for each file print, under `temp/logs/`.
+    pub log: bool,
+    pub timeout: Option<Duration>,
+    /// In megabytes.
+    pub memory_limit: Option<u64>,
+    /// In seconds.
+    pub cpu_limit: Option<u64>,
+}
+
+pub fn parse_defaults(data: &toml::Value) -> ParseResult<CommandDefaults> {
+    let shell = match data.get("shell") {
+        Some(toml::Value::String(s)) => Some(s.clone()),
+        Some(toml::Value::Boolean(true)) => Some(DEFAULT_SHELL.to_string()),
+        Some(toml::Value::Boolean(false)) | None => None,
+        Some(s) => return Err(ParseError::BadShell(s.to_string())),
+    };
+
+    let log = matches!(data.get("log_commands"), Some(toml::Value::Boolean(true)));
+
+    Ok(CommandDefaults {
+        shell,
+        log,
+        timeout: data.get("timeout").map(parse_timeout).transpose()?,
+        memory_limit: data
+            .get("memory_limit")
+            .map(|l| parse_limit("memory_limit", l, MAX_MEMORY_LIMIT))
+            .transpose()?,
+        cpu_limit: data
+            .get("cpu_limit")
+            .map(|l| parse_limit("cpu_limit", l, u64::MAX))
+            .transpose()?,
+    })
+}
+
+// A number of seconds, which may be fractional
+fn parse_timeout(timeout: &toml::Value) -> ParseResult<Duration> {
+    match timeout {
+        toml::Value::Integer(t) if *t > 0 => Ok(Duration::from_secs(*t as u64)),
+        toml::Value::Float(t) if *t > 0.0 => {
+            Duration::try_from_secs_f64(*t).map_err(|_| ParseError::BadTimeout(timeout.to_string()))
+        }
+        t => Err(ParseError::BadTimeout(t.to_string())),
+    }
+}
+
+fn parse_limit(name: &'static str, limit: &toml::Value, max: u64) -> ParseResult<u64> {
+    match limit {
+        toml::Value::Integer(l) if *l > 0 && *l as u64 <= max => Ok(*l as u64),
+        l => Err(ParseError::BadLimit(name, l.to_string())),
+    }
+}
+
+/// Parses the options shared by filters, pre- and post-commands. `table` is
+/// `None` for commands given as a plain string.
+pub fn parse_command(
+    command: &str,
+    table: Option<&toml::Table>,
+    site: &Site,
+    defaults: &CommandDefaults,
+) -> ParseResult<Command> {
+    let mut command = Command::new(site.substitute(command));
+
+    let shell = match table.and_then(|t| t.get("shell")) {
+        Some(toml::Value::String(s)) => Some(s.clone()),
+        Some(toml::Value::Boolean(true)) => defaults
+            .shell
+            .clone()
+            .or_else(|| Some(DEFAULT_SHELL.to_string())),
+        Some(toml::Value::Boolean(false)) => None,
+        Some(s) => return Err(ParseError::BadShell(s.to_string())),
+        None => defaults.shell.clone(),
+    };
+    command.set_shell(shell);
+    command.set_log(defaults.log);
+
+    let get = |key| table.and_then(|t| t.get(key));
+
+    match get("env") {
+        Some(toml::Value::Table(env)) => {
+            let mut vars = Vec::with_capacity(env.len());
+            for (key, value) in env {
+                let value = match value {
+                    toml::Value::String(s) => site.substitute(s),
+                    toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => {
+                        value.to_string()
+                    }
+                    _ => return Err(ParseError::BadEnv(key.clone())),
+                };
+
+                vars.push((key.clone(), value));
+            }
+
+            command.set_env(vars);
+        }
+        Some(_) => return Err(ParseError::BadEnvTable),
+        None => {}
+    }
+
+    match get("cwd") {
+        Some(toml::Value::String(cwd)) => command.set_cwd(Some(site.substitute(cwd))),
+        Some(_) => return Err(ParseError::BadCwd),
+        None => {}
+    }
+
+    command.set_timeout(match get("timeout") {
+        Some(t) => Some(parse_timeout(t)?),
+        None => defaults.timeout,
+    });
+    command.set_limits(
+        match get("memory_limit") {
+            Some(l) => Some(parse_limit("memory_limit", l, MAX_MEMORY_LIMIT)?),
+            None => defaults.memory_limit,
+        },
+        match get("cpu_limit") {
+            Some(l) => Some(parse_limit("cpu_limit", l, u64::MAX)?),
+            None => defaults.cpu_limit,
+        },
+    );
+
+    Ok(command)
+}
+
+/// Parses `pre_commands` or `post_commands`, whose items are either a command
+/// or a table with a `command` and its options.
+pub fn parse_commands(
+    commands: &Vec<toml::Value>,
+    site: &Site,
+    defaults: &CommandDefaults,
+) -> ParseResult<Vec<Command>> {
+    let mut new = Vec::new();
+    for i in commands {
+        match i {
+            toml::Value::String(command) => new.push(parse_command(command, None, site, defaults)?),
+            toml::Value::Table(table) => {
+                let command = field!(table, command, String);
+                new.push(parse_command(command, Some(table), site, defaults)?);
+            }
+            _ => return Err(ParseError::BadArrayItem),
+        }
+    }
+
+    Ok(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeouts() {
+        let timeout = |t: toml::Value| parse_timeout(&t).ok();
+
+        assert_eq!(timeout(30.into()), Some(Duration::from_secs(30)));
+        assert_eq!(timeout(0.5.into()), Some(Duration::from_millis(500)));
+
+        assert_eq!(timeout(0.into()), None);
+        assert_eq!(timeout((-1.0).into()), None);
+        assert_eq!(timeout(1e20.into()), None);
+        assert_eq!(timeout(f64::INFINITY.into()), None);
+        assert_eq!(timeout(f64::NAN.into()), None);
+        assert_eq!(timeout("30".into()), None);
+    }
+
+    #[test]
+    fn limits() {
+        let memory = |l: toml::Value| parse_limit("memory_limit", &l, MAX_MEMORY_LIMIT).ok();
+
+        assert_eq!(memory(512.into()), Some(512));
+        assert_eq!(
+            memory((MAX_MEMORY_LIMIT as i64).into()),
+            Some(MAX_MEMORY_LIMIT)
+        );
+
+        assert_eq!(memory(0.into()), None);
+        assert_eq!(memory((-1).into()), None);
+        assert_eq!(memory(i64::MAX.into()), None);
+        assert_eq!(memory(1.5.into()), None);
+    }
+}
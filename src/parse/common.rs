@@ -1,3 +1,6 @@
+use crate::error::*;
+use crate::paginate::Paginate;
+
 #[macro_export]
 macro_rules! field {
     ( $root:ident, $name:ident, $typ:ident ) => {
@@ -24,3 +27,20 @@ macro_rules! array {
         new
     }};
 }
+
+/// Parses the optional `paginate_by` and `paginate_path` keys.
+pub fn parse_paginate(data: &toml::Table) -> ParseResult<Option<Paginate>> {
+    let by = match data.get("paginate_by") {
+        Some(toml::Value::Integer(by)) if *by > 0 => *by as usize,
+        Some(_) => return Err(ParseError::BadPaginateBy),
+        None => return Ok(None),
+    };
+
+    let path = if let Some(toml::Value::String(p)) = &data.get("paginate_path") {
+        Some(p.clone())
+    } else {
+        None
+    };
+
+    Ok(Some(Paginate::new(by, path)))
+}
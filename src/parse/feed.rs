@@ -0,0 +1,55 @@
+use crate::error::*;
+use crate::feed::Feed;
+use crate::field;
+use crate::site::Site;
+
+pub fn parse_feed(data: &toml::Table, site: &Site) -> ParseResult<Feed> {
+    let rule = field!(data, rule, String);
+
+    let string = |key: &str| {
+        if let Some(toml::Value::String(s)) = &data.get(key) {
+            Some(s.clone())
+        } else {
+            None
+        }
+    };
+
+    let rss = string("rss");
+    let atom = string("atom");
+    if rss.is_none() && atom.is_none() {
+        return Err(ParseError::MissingField("rss"));
+    }
+
+    if site.base_url().is_none() {
+        return Err(ParseError::MissingBaseUrl("feeds"));
+    }
+
+    let limit = match data.get("limit") {
+        Some(toml::Value::Integer(l)) if *l > 0 => Some(*l as usize),
+        Some(_) => return Err(ParseError::BadFeedLimit),
+        None => None,
+    };
+
+    Feed::new(
+        rule,
+        rss,
+        atom,
+        string("title"),
+        string("description"),
+        limit,
+    )
+    .map_err(|_| ParseError::BadRegex(rule.clone()))
+}
+
+pub fn parse_feeds(feeds: &Vec<toml::Value>, site: &Site) -> ParseResult<Vec<Feed>> {
+    let mut new = Vec::new();
+    for i in feeds {
+        if let toml::Value::Table(v) = i {
+            new.push(parse_feed(v, site)?);
+        } else {
+            return Err(ParseError::BadArrayItem);
+        }
+    }
+
+    Ok(new)
+}
@@ -1,36 +1,92 @@
 use std::collections::HashMap;
 
+use crate::builtin::{Builtin, Highlight};
 use crate::error::*;
 use crate::field;
 use crate::filter::Filter;
+use crate::parse::command::{parse_command, CommandDefaults};
+use crate::site::Site;
 
-pub fn parse_filter(filter: &toml::Table) -> ParseResult<(Filter, Option<String>)> {
+pub fn parse_filter(
+    filter: &toml::Table,
+    site: &Site,
+    defaults: &CommandDefaults,
+) -> ParseResult<(Filter, Option<String>)> {
     let name = if let Some(toml::Value::String(s)) = &filter.get("name") {
         Some(s.clone())
     } else {
         None
     };
 
-    let command = field!(filter, command, String).clone();
     let outfile = if let Some(toml::Value::String(o)) = &filter.get("outfile") {
         Some(o.clone())
     } else {
         None
     };
 
+    let stdio = matches!(&filter.get("stdio"), Some(toml::Value::Boolean(true)));
+
+    if let Some(toml::Value::String(b)) = &filter.get("builtin") {
+        if stdio {
+            return Err(ParseError::BuiltinStdio(b.clone()));
+        }
+
+        let builtin = parse_builtin(b, filter)?;
+        let outfile = outfile.ok_or(ParseError::MissingField("outfile"))?;
+
+        return Ok((Filter::builtin(builtin, outfile), name));
+    }
+
+    let command = parse_command(
+        field!(filter, command, String),
+        Some(filter),
+        site,
+        defaults,
+    )?;
+
     let give_original = matches!(
         &filter.get("give_original"),
         Some(toml::Value::Boolean(true))
     ) && outfile.is_none();
 
-    Ok((Filter::new(command, outfile, give_original), name))
+    Ok((Filter::new(command, outfile, give_original, stdio), name))
 }
 
-pub fn parse_filters(filters: &Vec<toml::Value>) -> ParseResult<HashMap<String, Filter>> {
+fn parse_builtin(name: &str, filter: &toml::Table) -> ParseResult<Builtin> {
+    match name {
+        "markdown" => Ok(Builtin::Markdown),
+        "highlight" => {
+            let theme = if let Some(toml::Value::String(t)) = &filter.get("theme") {
+                Some(t.clone())
+            } else {
+                None
+            };
+
+            let css = if let Some(toml::Value::String(c)) = &filter.get("css") {
+                Some(c.clone())
+            } else {
+                None
+            };
+
+            let inline = matches!(&filter.get("inline"), Some(toml::Value::Boolean(true)));
+
+            Highlight::new(theme.clone(), inline, css)
+                .map(Builtin::Highlight)
+                .ok_or_else(|| ParseError::NoSuchTheme(theme.unwrap_or_default()))
+        }
+        _ => Err(ParseError::NoSuchBuiltin(name.to_string())),
+    }
+}
+
+pub fn parse_filters(
+    filters: &Vec<toml::Value>,
+    site: &Site,
+    defaults: &CommandDefaults,
+) -> ParseResult<HashMap<String, Filter>> {
     let mut new = HashMap::new();
     for i in filters {
         if let toml::Value::Table(v) = i {
-            let (filter, name) = parse_filter(v)?;
+            let (filter, name) = parse_filter(v, site, defaults)?;
             new.insert(name.ok_or(ParseError::MissingFilterName)?, filter);
         } else {
             return Err(ParseError::BadArrayItem);
@@ -39,3 +95,27 @@ pub fn parse_filters(filters: &Vec<toml::Value>) -> ParseResult<HashMap<String,
 
     Ok(new)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse;
+
+    use super::*;
+
+    #[test]
+    fn builtins_cant_stream() {
+        let rules = |filter: &str| format!("rules = []\nfilters = [{{{filter}}}]");
+
+        let parsed = parse(
+            rules(r#"name = "md", builtin = "markdown", outfile = "{name}.html", stdio = true"#),
+            None,
+        );
+        assert!(matches!(parsed, Err(ParseError::BuiltinStdio(b)) if b == "markdown"));
+
+        let parsed = parse(
+            rules(r#"name = "md", builtin = "markdown", outfile = "{name}.html", stdio = false"#),
+            None,
+        );
+        assert!(parsed.is_ok());
+    }
+}
@@ -1,48 +1,101 @@
+pub mod collection;
+pub mod command;
 pub mod common;
+pub mod feed;
 pub mod filter;
 pub mod rule;
+pub mod site;
+pub mod sitemap;
+pub mod taxonomy;
 
+use crate::collection::Collection;
 use crate::command::Command;
 use crate::error::*;
+use crate::feed::Feed;
 use crate::field;
-use crate::rule::Rule;
+use crate::rule::{Rule, Unmatched};
+use crate::site::Site;
+use crate::sitemap::Sitemap;
+use crate::taxonomy::Taxonomy;
 
 pub struct ParsedDataResult {
     pub rules: Vec<Rule>,
+    pub unmatched: Unmatched,
     pub pre_commands: Vec<Command>,
     pub post_commands: Vec<Command>,
+    pub site: Site,
+    pub collections: Vec<Collection>,
+    pub taxonomies: Vec<Taxonomy>,
+    pub feeds: Vec<Feed>,
+    pub sitemap: Option<Sitemap>,
 }
 
-pub fn parse(data: String) -> Result<ParsedDataResult, ParseError> {
+/// Parses `rules.toml`. If given, `base_url` overrides `site.base_url`.
+pub fn parse(data: String, base_url: Option<&str>) -> Result<ParsedDataResult, ParseError> {
     let data: toml::Value = toml::from_str(&data).map_err(ParseError::TomlError)?;
 
+    let mut site = site::parse_site(data.get("site"))?;
+    if let Some(base_url) = base_url {
+        site.set("base_url", base_url.to_string());
+    }
+
     let filters = field!(data, filters, Array);
     let rules = field!(data, rules, Array);
 
+    let defaults = command::parse_defaults(&data)?;
+
     let pre_commands = if let Some(toml::Value::Array(commands)) = &data.get("pre_commands") {
-        commands
-            .iter()
-            .filter_map(|v| v.as_str().map(|command| Command::new(command.to_string())))
-            .collect()
+        command::parse_commands(commands, &site, &defaults)?
     } else {
         Vec::new()
     };
 
     let post_commands = if let Some(toml::Value::Array(commands)) = &data.get("post_commands") {
-        commands
-            .iter()
-            .filter_map(|v| v.as_str().map(|command| Command::new(command.to_string())))
-            .collect()
+        command::parse_commands(commands, &site, &defaults)?
     } else {
         Vec::new()
     };
 
-    let filters = filter::parse_filters(filters)?;
-    let rules = rule::parse_rules(rules, &filters)?;
+    let collections = if let Some(toml::Value::Array(c)) = &data.get("collections") {
+        collection::parse_collections(c)?
+    } else {
+        Vec::new()
+    };
+
+    let taxonomies = if let Some(toml::Value::Array(t)) = &data.get("taxonomies") {
+        taxonomy::parse_taxonomies(t, &collections)?
+    } else {
+        Vec::new()
+    };
+
+    let feeds = if let Some(toml::Value::Array(f)) = &data.get("feeds") {
+        feed::parse_feeds(f, &site)?
+    } else {
+        Vec::new()
+    };
+
+    let sitemap = sitemap::parse_sitemap(data.get("sitemap"), &site)?;
+
+    let unmatched = match data.get("unmatched") {
+        Some(toml::Value::String(u)) => {
+            Unmatched::from_name(u).ok_or_else(|| ParseError::BadUnmatched(u.clone()))?
+        }
+        Some(u) => return Err(ParseError::BadUnmatched(u.to_string())),
+        None => Unmatched::default(),
+    };
+
+    let filters = filter::parse_filters(filters, &site, &defaults)?;
+    let rules = rule::parse_rules(rules, &filters, &site, &defaults)?;
 
     Ok(ParsedDataResult {
         rules,
+        unmatched,
         pre_commands,
         post_commands,
+        site,
+        collections,
+        taxonomies,
+        feeds,
+        sitemap,
     })
 }
@@ -2,12 +2,18 @@ use std::collections::HashMap;
 
 use crate::error::*;
 use crate::filter::Filter;
+use crate::parse::command::CommandDefaults;
 use crate::parse::filter::parse_filter;
 use crate::rule::Rule;
+use crate::site::Site;
 use crate::{array, field};
 
-pub fn parse_rule(data: &toml::Table, filter_map: &HashMap<String, Filter>) -> ParseResult<Rule> {
-    let rule = field!(data, rule, String);
+pub fn parse_rule(
+    data: &toml::Table,
+    filter_map: &HashMap<String, Filter>,
+    site: &Site,
+    defaults: &CommandDefaults,
+) -> ParseResult<Rule> {
     let fs = field!(data, filters, Array);
     let templates = array!(data, templates, String);
     let output = if let Some(toml::Value::String(o)) = &data.get("output") {
@@ -25,11 +31,31 @@ pub fn parse_rule(data: &toml::Table, filter_map: &HashMap<String, Filter>) -> P
                 return Err(ParseError::NoSuchFilter(f.clone()));
             }
         } else if let toml::Value::Table(filter) = filter {
-            filters.push(parse_filter(filter)?.0);
+            filters.push(parse_filter(filter, site, defaults)?.0);
         }
     }
 
-    let mut rule = Rule::new(rule, output).map_err(|_| ParseError::BadRegex(rule.clone()))?;
+    let mut rule = match (data.get("rule"), data.get("glob")) {
+        (Some(toml::Value::String(rule)), None) => {
+            Rule::new(rule, output).map_err(|_| ParseError::BadRegex(rule.clone()))?
+        }
+        (None, Some(toml::Value::String(glob))) => {
+            Rule::glob(glob, output).map_err(|e| ParseError::BadGlob(glob.clone(), e))?
+        }
+        (Some(_), Some(_)) => return Err(ParseError::RuleAndGlob),
+        _ => return Err(ParseError::MissingField("rule")),
+    };
+
+    if data.contains_key("exclude") {
+        let exclude = array!(data, exclude, String);
+        rule.exclude_all(&exclude)
+            .map_err(|e| ParseError::BadGlob(e.glob().unwrap_or_default().to_string(), e))?;
+    }
+
+    rule.set_continues(matches!(
+        &data.get("continue"),
+        Some(toml::Value::Boolean(true))
+    ));
     rule.filter_all(filters);
     rule.template_all(templates.into_iter().cloned().collect());
 
@@ -39,11 +65,13 @@ pub fn parse_rule(data: &toml::Table, filter_map: &HashMap<String, Filter>) -> P
 pub fn parse_rules(
     rules: &Vec<toml::Value>,
     filters: &HashMap<String, Filter>,
+    site: &Site,
+    defaults: &CommandDefaults,
 ) -> ParseResult<Vec<Rule>> {
     let mut new = Vec::new();
     for i in rules {
         if let toml::Value::Table(v) = i {
-            new.push(parse_rule(v, filters)?);
+            new.push(parse_rule(v, filters, site, defaults)?);
         } else {
             return Err(ParseError::BadArrayItem);
         }
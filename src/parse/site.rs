@@ -0,0 +1,18 @@
+use crate::error::*;
+use crate::site::Site;
+
+pub fn parse_site(site: Option<&toml::Value>) -> ParseResult<Site> {
+    let site = match site {
+        Some(toml::Value::Table(t)) => t.clone(),
+        Some(_) => return Err(ParseError::BadSiteTable),
+        None => toml::Table::new(),
+    };
+
+    for key in ["title", "base_url", "language", "author"] {
+        if site.get(key).is_some_and(|v| !v.is_str()) {
+            return Err(ParseError::BadSiteField(key));
+        }
+    }
+
+    Ok(Site::new(site))
+}
@@ -0,0 +1,25 @@
+use crate::error::*;
+use crate::site::Site;
+use crate::sitemap::Sitemap;
+
+pub fn parse_sitemap(sitemap: Option<&toml::Value>, site: &Site) -> ParseResult<Option<Sitemap>> {
+    let sitemap = match sitemap {
+        Some(toml::Value::Table(t)) => t,
+        Some(_) => return Err(ParseError::BadSitemapTable),
+        None => return Ok(None),
+    };
+
+    if site.base_url().is_none() {
+        return Err(ParseError::MissingBaseUrl("a sitemap"));
+    }
+
+    let path = if let Some(toml::Value::String(p)) = &sitemap.get("path") {
+        Some(p.clone())
+    } else {
+        None
+    };
+
+    let robots = !matches!(&sitemap.get("robots"), Some(toml::Value::Boolean(false)));
+
+    Ok(Some(Sitemap::new(path, robots)))
+}
@@ -0,0 +1,55 @@
+use crate::collection::Collection;
+use crate::error::*;
+use crate::field;
+use crate::parse::common::parse_paginate;
+use crate::taxonomy::Taxonomy;
+
+pub fn parse_taxonomy(data: &toml::Table, collections: &[Collection]) -> ParseResult<Taxonomy> {
+    let name = field!(data, name, String);
+    let template = field!(data, template, String);
+    let output = field!(data, output, String);
+
+    let collection = if let Some(toml::Value::String(c)) = &data.get("collection") {
+        if !collections.iter().any(|col| col.name() == c) {
+            return Err(ParseError::NoSuchCollection(c.clone()));
+        }
+
+        Some(c.clone())
+    } else {
+        None
+    };
+
+    let overview = match (data.get("overview_template"), data.get("overview_output")) {
+        (Some(toml::Value::String(t)), Some(toml::Value::String(o))) => {
+            Some((t.clone(), o.clone()))
+        }
+        (None, None) => None,
+        (Some(_), _) => return Err(ParseError::MissingField("overview_output")),
+        (None, _) => return Err(ParseError::MissingField("overview_template")),
+    };
+
+    Ok(Taxonomy::new(
+        name.clone(),
+        collection,
+        template.clone(),
+        output.clone(),
+        overview,
+        parse_paginate(data)?,
+    ))
+}
+
+pub fn parse_taxonomies(
+    taxonomies: &Vec<toml::Value>,
+    collections: &[Collection],
+) -> ParseResult<Vec<Taxonomy>> {
+    let mut new = Vec::new();
+    for i in taxonomies {
+        if let toml::Value::Table(v) = i {
+            new.push(parse_taxonomy(v, collections)?);
+        } else {
+            return Err(ParseError::BadArrayItem);
+        }
+    }
+
+    Ok(new)
+}
@@ -1,167 +1,648 @@
-use std::fs::{copy, create_dir_all, read_to_string, OpenOptions};
+use std::cell::Cell;
+use std::fs::{copy, create_dir_all, read, read_to_string, OpenOptions};
 use std::io::Write;
 use std::str::FromStr;
 
 use fancy_regex::Regex;
-use log::{debug, error};
+use globset::{GlobBuilder, GlobMatcher};
+use log::{debug, error, warn};
+use serde_yaml::{Mapping, Value};
 use yaml_front_matter::YamlFrontMatter;
 
+use crate::command;
 use crate::filepath::FilePath;
-use crate::filter::{substitute, Filter};
-use crate::tempfile::tempdir;
-use crate::template::apply_template;
+use crate::filter::Filter;
+use crate::page::Page;
+use crate::tempfile::{self, tempdir};
+use crate::template::Templates;
+
+/// What to do with content files that no rule matches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Unmatched {
+    #[default]
+    Ignore,
+    Warn,
+    Error,
+    Copy,
+}
+
+impl Unmatched {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ignore" => Some(Self::Ignore),
+            "warn" => Some(Self::Warn),
+            "error" => Some(Self::Error),
+            "copy" => Some(Self::Copy),
+            _ => None,
+        }
+    }
+}
+
+/// What a rule or collection matches files with.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Matched against the full path, including the content directory.
+    Regex(Regex),
+    /// Matched against the path relative to the content directory.
+    Glob(GlobMatcher),
+}
+
+impl Pattern {
+    pub fn regex(regex: &str) -> Result<Self, fancy_regex::Error> {
+        Ok(Self::Regex(Regex::new(regex)?))
+    }
+
+    pub fn glob(glob: &str) -> Result<Self, globset::Error> {
+        Ok(Self::Glob(glob_matcher(glob)?))
+    }
+
+    /// The property the pattern is given by in `rules.toml`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Regex(_) => "rule",
+            Self::Glob(_) => "glob",
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Regex(regex) => regex.as_str(),
+            Self::Glob(glob) => glob.glob().glob(),
+        }
+    }
+
+    pub fn is_match(&self, filepath: &FilePath, content: &str) -> bool {
+        match self {
+            Self::Regex(regex) => regex.is_match(&filepath.full()).unwrap(),
+            Self::Glob(glob) => glob.is_match(relative(filepath, content)),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Rule {
-    rule: Regex,
+    rule: Pattern,
+    exclude: Vec<GlobMatcher>,
     filters: Vec<Filter>,
     templates: Vec<String>,
     output: Option<String>,
+    continues: bool,
 }
 
 impl Rule {
     pub fn new(rule: &str, output: Option<String>) -> Result<Self, fancy_regex::Error> {
         Ok(Self {
-            rule: Regex::new(rule)?,
+            rule: Pattern::regex(rule)?,
+            exclude: Vec::new(),
             filters: Vec::new(),
             templates: Vec::new(),
             output,
+            continues: false,
         })
     }
 
+    /// A rule matching files by a glob relative to the content directory, such
+    /// as `blog/**/*.md`.
+    pub fn glob(glob: &str, output: Option<String>) -> Result<Self, globset::Error> {
+        Ok(Self {
+            rule: Pattern::glob(glob)?,
+            exclude: Vec::new(),
+            filters: Vec::new(),
+            templates: Vec::new(),
+            output,
+            continues: false,
+        })
+    }
+
+    /// Excludes files matching any of `globs` (relative to the content
+    /// directory), even if the rule matches them.
+    pub fn exclude_all(&mut self, globs: &[&String]) -> Result<(), globset::Error> {
+        for glob in globs {
+            self.exclude.push(glob_matcher(glob)?);
+        }
+
+        Ok(())
+    }
+
+    /// Lets later rules match the same file, instead of stopping at this one.
+    pub fn set_continues(&mut self, continues: bool) {
+        self.continues = continues;
+    }
+
+    pub fn continues(&self) -> bool {
+        self.continues
+    }
+
     pub fn filter_all(&mut self, mut filters: Vec<Filter>) {
         self.filters.append(&mut filters);
     }
 
+    /// The regex or glob this rule matches files with.
+    pub fn pattern(&self) -> &str {
+        self.rule.as_str()
+    }
+
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    pub fn templates(&self) -> &[String] {
+        &self.templates
+    }
+
+    /// Everything about the rule that affects its outputs, so that changes to
+    /// it in `rules.toml` can be noticed.
+    pub fn fingerprint(&self) -> String {
+        let exclude = self
+            .exclude
+            .iter()
+            .map(|g| g.glob().glob())
+            .collect::<Vec<_>>();
+
+        format!(
+            "{} {:?} {exclude:?} {:?} {:?} {:?} {}",
+            self.rule.kind(),
+            self.pattern(),
+            self.filters,
+            self.templates,
+            self.output,
+            self.continues
+        )
+    }
+
     pub fn template_all(&mut self, mut templates: Vec<String>) {
         self.templates.append(&mut templates);
     }
 
-    pub fn matches(&self, filepath: &FilePath) -> bool {
-        self.rule.is_match(&filepath.full()).unwrap()
+    pub fn matches(&self, filepath: &FilePath, content: &str) -> bool {
+        self.rule.is_match(filepath, content) && !self.excludes(filepath, content)
+    }
+
+    /// Whether one of the `exclude` patterns matches `filepath`.
+    pub fn excludes(&self, filepath: &FilePath, content: &str) -> bool {
+        let relative = relative(filepath, content);
+        self.exclude.iter().any(|g| g.is_match(&relative))
     }
 
     pub fn has_output(&self) -> bool {
         self.output.is_some()
     }
 
-    pub fn out(&self, path: &FilePath) -> Result<FilePath, &str> {
-        FilePath::from_str(&substitute(self.output.as_ref().unwrap(), path))
+    /// Where the rule puts `path`, given its frontmatter. Frontmatter values
+    /// can't contain path separators or `..`, so that they can't put the file
+    /// outside of the output directory.
+    pub fn out(&self, path: &FilePath, meta: &Mapping) -> Result<FilePath, String> {
+        let bad = Cell::new(None);
+        let output = command::substitute_meta(self.output.as_ref().unwrap(), path, None, |key| {
+            let value = meta.get(key).map(command::meta_string)?;
+            if value.contains(['/', '\\']) || value.contains("..") {
+                bad.set(Some(key.to_string()));
+            }
+
+            Some(value)
+        });
+
+        if let Some(key) = bad.take() {
+            return Err(format!(
+                "frontmatter `{key}` can't contain `/`, `\\` or `..` when used in `output`"
+            ));
+        }
+
+        FilePath::from_str(&output).map_err(String::from)
+    }
+
+    // The path of the file after all filters have run, i.e. what the templates
+    // get applied to.
+    fn rendered(&self, path: &FilePath) -> Result<FilePath, String> {
+        let temp = tempfile::ROOT;
+        let mut cwpath = tempdir(temp, &format!("{path}-yamlless"), path);
+        for filter in &self.filters {
+            if filter.stdio {
+                cwpath = filter.stdio_path(temp, &cwpath)?;
+            } else if filter.has_outfile() {
+                cwpath = filter.tempdir(temp, &cwpath)?;
+            }
+        }
+
+        Ok(cwpath)
+    }
+
+    /// Collects the information about a file needed by listing pages, using
+    /// only its frontmatter, so the page's `content` is left empty. Returns
+    /// `None` if this rule doesn't produce an output.
+    pub fn page_meta(&self, path: &FilePath, content: &String) -> Option<Page> {
+        if !self.has_output() {
+            return None;
+        }
+
+        let data = match read_to_string(path.full()) {
+            Ok(d) => d,
+            Err(e) => {
+                error!("Failed to open file {}: {}", path, e);
+                return None;
+            }
+        };
+
+        let (meta, _) = frontmatter(data);
+
+        let output = match self.out(path, &meta) {
+            Ok(f) => f.strip_prefix(content),
+            Err(e) => {
+                error!("Invalid output path for {}: {}", path, e);
+                return None;
+            }
+        };
+
+        Some(Page {
+            source: path.clone(),
+            output,
+            meta,
+            content: String::new(),
+        })
+    }
+
+    /// Collects the information about an already-built file needed by listing
+    /// pages, including its content. Returns `None` if this rule doesn't
+    /// produce an output.
+    pub fn page(&self, path: &FilePath, content: &String) -> Option<Page> {
+        let mut page = self.page_meta(path, content)?;
+        page.content = match self.rendered(path).map(|p| read_to_string(p.full())) {
+            Ok(Ok(r)) => r,
+            Ok(Err(e)) => {
+                warn!("Failed to read rendered content of {}: {}", path, e);
+                String::new()
+            }
+            Err(e) => {
+                warn!("Failed to find rendered content of {}: {}", path, e);
+                String::new()
+            }
+        };
+
+        Some(page)
     }
 
-    pub fn exec(&self, path: FilePath, content: &String, output: &String) -> bool {
+    /// Runs the rule on a file, returning the files written to the output
+    /// directory, or `None` if it failed. Temporary files go in `temp`, and
+    /// each step is described to `trace`, if given.
+    pub fn exec(
+        &self,
+        path: FilePath,
+        content: &String,
+        output: &String,
+        temp: &str,
+        templates: &Templates,
+        mut trace: Option<&mut dyn Write>,
+    ) -> Option<Vec<FilePath>> {
+        macro_rules! trace {
+            ($($arg:tt)*) => {
+                if let Some(trace) = trace.as_mut() {
+                    _ = writeln!(trace, $($arg)*);
+                }
+            };
+        }
+
+        let mut written = Vec::new();
         let mut cwpath;
+        // The working file, while it's only in memory
+        let mut pending = None;
         let mut yaml = None;
         if self.has_output() {
-            let data = match read_to_string(&path.full()) {
+            let data = match read_to_string(path.full()) {
                 Ok(d) => d,
                 Err(e) => {
                     error!("Failed to open file {}: {}", path, e);
-                    return false;
+                    return None;
                 }
             };
 
-            let data = match YamlFrontMatter::parse::<serde_yaml::Mapping>(&data) {
-                Ok(y) => {
-                    yaml = Some(y.metadata);
-                    y.content
-                }
-                Err(_) => {
-                    yaml = Some(serde_yaml::Mapping::new());
-                    data
-                }
-            };
+            let (meta, data) = frontmatter(data);
+            let keys = meta
+                .keys()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+            trace!(
+                "Frontmatter keys: {}",
+                if keys.is_empty() { "none" } else { &keys }
+            );
+            yaml = Some(meta);
 
-            cwpath = tempdir(&format!("{path}-yamlless"), &path);
+            cwpath = tempdir(temp, &format!("{path}-yamlless"), &path);
+            trace!("Without frontmatter: `{cwpath}`");
 
-            if let Err(e) = create_dir_all(cwpath.dir()) {
-                error!("Failed to create parent directories: {}", e);
-                return false;
-            }
+            pending = Some(data.into_bytes());
+        } else {
+            cwpath = path.clone();
+        }
 
-            match OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(&cwpath.full())
-            {
-                Ok(mut f) => {
-                    if let Err(e) = f.write_all(data.as_bytes()) {
-                        error!("Failed to write to file {}: {}", cwpath, e);
-                        return false;
+        let env = command::environment(Some(&path), output, yaml.as_ref());
+        let log = command::log_path(temp, &path.full());
+        for (i, filter) in self.filters.iter().enumerate() {
+            if filter.stdio {
+                let out = match filter.stdio_path(temp, &cwpath) {
+                    Ok(out) => out,
+                    Err(e) => {
+                        error!("{}", e);
+                        return None;
                     }
+                };
+
+                trace!(
+                    "Filter #{}: `{}`",
+                    i + 1,
+                    filter.describe(temp, &path, yaml.as_ref())
+                );
+                match (filter.give_original, &pending) {
+                    (true, _) => trace!("    input: `{path}`, on stdin"),
+                    (false, Some(_)) => trace!("    input: in memory, on stdin"),
+                    (false, None) => trace!("    input: `{cwpath}`, on stdin"),
                 }
-                Err(e) => {
-                    error!("Failed to open file {}: {}", cwpath, e);
-                    return false;
-                }
+                trace!("    output: in memory, from stdout");
+
+                let input = if filter.give_original {
+                    read_file(&path)?
+                } else if let Some(data) = pending.take() {
+                    data
+                } else {
+                    read_file(&cwpath)?
+                };
+
+                pending = Some(filter.pipe(&path, input, &env, yaml.as_ref(), &log)?);
+                cwpath = out;
+                continue;
+            }
+
+            // Everything else needs a file to work with
+            if let Some(data) = pending.take() {
+                write_file(&cwpath, &data)?;
             }
-        } else {
-            cwpath = path.clone();
-        }
 
-        for filter in &self.filters {
             let given_path = if filter.give_original { &path } else { &cwpath };
 
-            if !filter.exec(given_path) {
-                return false;
+            trace!(
+                "Filter #{}: `{}`",
+                i + 1,
+                filter.describe(temp, given_path, yaml.as_ref())
+            );
+            trace!("    input: `{given_path}`");
+            match filter.has_outfile().then(|| filter.tempdir(temp, &cwpath)) {
+                Some(Ok(out)) => trace!("    output: `{out}`"),
+                Some(Err(e)) => trace!("    output: {e}"),
+                None => trace!("    output: none"),
+            }
+
+            if !filter.exec(temp, given_path, &env, yaml.as_ref(), &log) {
+                return None;
             }
 
             if filter.has_outfile() {
-                cwpath = filter.tempdir(&cwpath).unwrap();
+                cwpath = filter.tempdir(temp, &cwpath).unwrap();
             }
         }
 
-        if self.has_output() {
-            for template in &self.templates {
-                debug!("Applying template file {} to {}", template, cwpath);
+        // Templates need a file, and listing pages read it later
+        if let Some(data) = pending.take() {
+            write_file(&cwpath, &data)?;
+            trace!("Written to `{cwpath}`");
+        }
 
-                let out = tempdir(template, &cwpath);
+        if self.has_output() {
+            let yaml = yaml.unwrap();
 
-                if let Err(e) = create_dir_all(out.dir()) {
-                    error!("Failed to create parent directories: {}", e);
-                    return false;
+            let first = match self.out(&path, &yaml) {
+                Ok(f) => f.strip_prefix(content),
+                Err(e) => {
+                    error!("Failed to create final file: {}", e);
+                    return None;
                 }
+            };
 
-                if let Err(e) = create_dir_all(out.dir()) {
-                    error!(
-                        "Failed to create tempfile directory structure for template: {}",
-                        e
-                    );
-                    return false;
+            // Listing pages may be split into several outputs
+            let pages = match yaml.get("paginate") {
+                Some(Value::String(collection)) => match templates.paginate(collection, &first) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        error!("Failed to paginate: {}", e);
+                        return None;
+                    }
+                },
+                _ => vec![(first, Value::Null)],
+            };
+
+            let rendered = cwpath;
+            if pages.len() > 1 {
+                trace!("Paginated into {} pages", pages.len());
+            }
+
+            for (out, paginator) in pages {
+                let mut vars = yaml.clone();
+                if !paginator.is_null() {
+                    vars.insert("paginator".into(), paginator);
                 }
 
-                if let Err(e) =
-                    apply_template(template, &cwpath.full(), out.full(), yaml.as_ref().unwrap())
-                {
-                    error!("Failed to apply template: {}", e);
-                    return false;
+                let mut cwpath = rendered.clone();
+                for template in &self.templates {
+                    debug!("Applying template file {} to {}", template, cwpath);
+
+                    let out = tempdir(temp, template, &cwpath);
+
+                    if let Err(e) = create_dir_all(out.dir()) {
+                        error!(
+                            "Failed to create tempfile directory structure for template: {}",
+                            e
+                        );
+                        return None;
+                    }
+
+                    if let Err(e) = templates.apply(template, cwpath.full(), out.full(), &vars) {
+                        error!("Failed to apply template: {}", e);
+                        return None;
+                    }
+
+                    trace!("Template `{template}`: `{out}`");
+
+                    cwpath = out;
                 }
 
-                cwpath = tempdir(template, &cwpath);
-            }
+                let out = out.prefix(output);
+                trace!("Output: `{out}`");
 
-            let out = match FilePath::from_str(&substitute(self.output.as_ref().unwrap(), &path)) {
-                Ok(f) => f.strip_prefix(content).prefix(output),
-                Err(e) => {
-                    error!("Failed to create final file: {}", e);
-                    return false;
+                if let Err(e) = create_dir_all(out.dir()) {
+                    error!("Failed to create final file parent directories: {}", e);
+                    return None;
                 }
-            };
 
-            if let Err(e) = create_dir_all(out.dir()) {
-                error!("Failed to create final file parent directories: {}", e);
-                return false;
-            }
+                if let Err(e) = copy(cwpath.full(), out.full()) {
+                    error!("Failed to finalize file output: {}", e);
+                    return None;
+                }
 
-            if let Err(e) = copy(cwpath.full(), out.full()) {
-                error!("Failed to finalize file output: {}", e);
-                return false;
+                written.push(out);
             }
         } else {
             debug!("No output file for this rule, skipping templates");
+            trace!("Output: none, the rule has no `output`");
+        }
+
+        Some(written)
+    }
+}
+
+/// The rules to apply to `filepath`, in order: every matching rule up to and
+/// including the first one without `continue = true`.
+pub fn matching<'a>(rules: &'a [Rule], filepath: &FilePath, content: &str) -> Vec<&'a Rule> {
+    let mut matched = Vec::new();
+    for rule in rules.iter().filter(|r| r.matches(filepath, content)) {
+        matched.push(rule);
+        if !rule.continues() {
+            break;
+        }
+    }
+
+    matched
+}
+
+fn glob_matcher(glob: &str) -> Result<GlobMatcher, globset::Error> {
+    GlobBuilder::new(glob.trim_start_matches('/'))
+        .literal_separator(true)
+        .build()
+        .map(|g| g.compile_matcher())
+}
+
+fn read_file(path: &FilePath) -> Option<Vec<u8>> {
+    match read(path.full()) {
+        Ok(d) => Some(d),
+        Err(e) => {
+            error!("Failed to open file {}: {}", path, e);
+            None
+        }
+    }
+}
+
+fn write_file(path: &FilePath, data: &[u8]) -> Option<()> {
+    if let Err(e) = create_dir_all(path.dir()) {
+        error!("Failed to create parent directories: {}", e);
+        return None;
+    }
+
+    match OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path.full())
+    {
+        Ok(mut f) => {
+            if let Err(e) = f.write_all(data) {
+                error!("Failed to write to file {}: {}", path, e);
+                return None;
+            }
+        }
+        Err(e) => {
+            error!("Failed to open file {}: {}", path, e);
+            return None;
+        }
+    }
+
+    Some(())
+}
+
+// The path relative to the content directory, which globs match against.
+fn relative(filepath: &FilePath, content: &str) -> String {
+    filepath.clone().strip_prefix(content).full()
+}
+
+/// Splits off the YAML frontmatter, if any.
+pub fn frontmatter(data: String) -> (serde_yaml::Mapping, String) {
+    match YamlFrontMatter::parse::<serde_yaml::Mapping>(&data) {
+        Ok(y) => (y.metadata, y.content),
+        Err(_) => (serde_yaml::Mapping::new(), data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(p: &str) -> FilePath {
+        FilePath::from_str(p).unwrap()
+    }
+
+    fn slug(slug: &str) -> Mapping {
+        let mut meta = Mapping::new();
+        meta.insert("slug".into(), slug.into());
+        meta
+    }
+
+    #[test]
+    fn output_from_frontmatter() {
+        let rule = Rule::new(".*", Some("{dir}/{meta.slug|name}/index.html".into())).unwrap();
+        let post = path("content/blog/post.md");
+
+        assert_eq!(
+            rule.out(&post, &Mapping::new()).unwrap().full(),
+            "content/blog/post/index.html"
+        );
+        assert_eq!(
+            rule.out(&post, &slug("hello-world")).unwrap().full(),
+            "content/blog/hello-world/index.html"
+        );
+    }
+
+    #[test]
+    fn output_stays_in_output_directory() {
+        let rule = Rule::new(".*", Some("{dir}/{meta.slug|name}/index.html".into())).unwrap();
+        let post = path("content/blog/post.md");
+
+        for bad in ["../../etc/x", "..", "a/b", "a\\b"] {
+            assert!(rule.out(&post, &slug(bad)).is_err(), "{bad}");
         }
+    }
+
+    fn patterns(rules: &[Rule], file: &str) -> Vec<String> {
+        matching(rules, &path(file), "content")
+            .iter()
+            .map(|r| r.pattern().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn excluded_files_fall_through() {
+        let mut blog = Rule::glob("blog/**", None).unwrap();
+        let drafts = String::from("blog/drafts/**");
+        let index = String::from("**/index.md");
+        blog.exclude_all(&[&drafts, &index]).unwrap();
+        let rules = [blog, Rule::new(".*", None).unwrap()];
+
+        assert_eq!(patterns(&rules, "content/blog/post.md"), ["blog/**"]);
+        assert_eq!(patterns(&rules, "content/blog/drafts/wip.md"), [".*"]);
+        assert_eq!(patterns(&rules, "content/blog/index.md"), [".*"]);
+        assert!(rules[0].excludes(&path("content/blog/drafts/wip.md"), "content"));
+    }
+
+    #[test]
+    fn continuing_rules() {
+        let mut all = Rule::new(".*", None).unwrap();
+        all.set_continues(true);
+        let mut markdown = Rule::glob("**/*.md", None).unwrap();
+        markdown.set_continues(true);
+        let rules = [
+            all,
+            markdown,
+            Rule::glob("blog/*", None).unwrap(),
+            Rule::glob("**", None).unwrap(),
+        ];
+
+        assert_eq!(
+            patterns(&rules, "content/blog/post.md"),
+            [".*", "**/*.md", "blog/*"]
+        );
+        assert_eq!(
+            patterns(&rules, "content/about.md"),
+            [".*", "**/*.md", "**"]
+        );
+        assert_eq!(patterns(&rules, "content/style.css"), [".*", "**"]);
 
-        true
+        // Continuing rules alone still match
+        assert_eq!(patterns(&rules[..2], "content/style.css"), [".*"]);
     }
 }
@@ -0,0 +1,329 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8000";
+
+const RELOAD_PATH: &str = "/__rssg/reload";
+const RELOAD_SCRIPT: &str =
+    "<script>new EventSource(\"/__rssg/reload\").onmessage = () => location.reload();</script>";
+
+// How often to ping idle live-reload connections, to notice closed tabs
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Notifies connected browsers that a build finished.
+#[derive(Default)]
+pub struct Reloader {
+    generation: Mutex<u64>,
+    cond: Condvar,
+}
+
+impl Reloader {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn reload(&self) {
+        *self.generation.lock().unwrap() += 1;
+        self.cond.notify_all();
+    }
+
+    fn current(&self) -> u64 {
+        *self.generation.lock().unwrap()
+    }
+
+    // Waits until the generation moves past `seen`, or the ping interval
+    // elapses. Returns the current generation.
+    fn wait(&self, seen: u64) -> u64 {
+        let generation = self.generation.lock().unwrap();
+        let (generation, _) = self
+            .cond
+            .wait_timeout_while(generation, PING_INTERVAL, |g| *g == seen)
+            .unwrap();
+
+        *generation
+    }
+}
+
+/// Serves `output` over HTTP on `addr` in a background thread, injecting a
+/// live-reload script into HTML pages.
+pub fn serve(addr: &str, output: String, reloader: Arc<Reloader>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!(
+        "Serving `{}/` at http://{}/",
+        output,
+        listener.local_addr()?
+    );
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+
+            let output = output.clone();
+            let reloader = reloader.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle(stream, &output, &reloader) {
+                    debug!("Connection closed: {}", e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream, output: &str, reloader: &Reloader) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    // Skip the headers, we don't need any of them
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    }
+
+    if path == RELOAD_PATH {
+        return live_reload(stream, reloader);
+    }
+
+    debug!("Serving `{}`", path);
+    let file = match resolve(output, &decode(path)) {
+        Some(Resolved::File(file)) => file,
+        Some(Resolved::Directory) => {
+            let location = format!("{path}/{}", &target[path.len()..]);
+            return redirect(&mut stream, &location);
+        }
+        None => return respond(&mut stream, "404 Not Found", "text/plain", b"404 Not Found"),
+    };
+
+    let mut body = match fs::read(&file) {
+        Ok(b) => b,
+        Err(e) => {
+            warn!("Failed to read `{}`: {}", file.display(), e);
+            return respond(
+                &mut stream,
+                "500 Internal Server Error",
+                "text/plain",
+                b"500 Internal Server Error",
+            );
+        }
+    };
+
+    let mime = mime_type(&file);
+    if mime.starts_with("text/html") {
+        body = inject_script(body);
+    }
+
+    if method == "HEAD" {
+        body.clear();
+    }
+
+    respond(&mut stream, "200 OK", mime, &body)
+}
+
+fn respond(stream: &mut TcpStream, status: &str, mime: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {mime}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn redirect(stream: &mut TcpStream, location: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 301 Moved Permanently\r\nLocation: {location}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+    )?;
+    stream.flush()
+}
+
+fn live_reload(mut stream: TcpStream, reloader: &Reloader) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n"
+    )?;
+    stream.flush()?;
+
+    let mut seen = reloader.current();
+    loop {
+        let generation = reloader.wait(seen);
+        if generation == seen {
+            stream.write_all(b": ping\n\n")?;
+        } else {
+            seen = generation;
+            stream.write_all(b"data: reload\n\n")?;
+        }
+
+        stream.flush()?;
+    }
+}
+
+// What a request path refers to in `output`
+#[derive(Debug, PartialEq)]
+enum Resolved {
+    File(PathBuf),
+    // A directory with an index, asked for without a trailing slash. It's
+    // redirected, so that relative links in the index work.
+    Directory,
+}
+
+// Maps a request path onto a file in `output`, refusing to leave it.
+fn resolve(output: &str, path: &str) -> Option<Resolved> {
+    let mut file = PathBuf::from(output);
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(c) => file.push(c),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    if file.is_dir() {
+        file.push("index.html");
+        if !path.is_empty() && !path.ends_with('/') {
+            return file.is_file().then_some(Resolved::Directory);
+        }
+    }
+
+    file.is_file().then_some(Resolved::File(file))
+}
+
+fn decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = path
+                .get(i + 1..i + 3)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+            {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn inject_script(body: Vec<u8>) -> Vec<u8> {
+    let html = String::from_utf8_lossy(&body);
+    match html.rfind("</body>") {
+        Some(i) => format!("{}{RELOAD_SCRIPT}{}", &html[..i], &html[i..]).into_bytes(),
+        None => format!("{html}{RELOAD_SCRIPT}").into_bytes(),
+    }
+}
+
+fn mime_type(file: &Path) -> &'static str {
+    match file
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .as_deref()
+    {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_inside_output() {
+        let root = std::env::temp_dir().join(format!("rssg-serve-{}", std::process::id()));
+        let output = root.join("output");
+        fs::create_dir_all(output.join("blog")).unwrap();
+        fs::write(output.join("index.html"), "home").unwrap();
+        fs::write(output.join("blog/index.html"), "blog").unwrap();
+        fs::write(output.join("style.css"), "css").unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+
+        let out = output.display().to_string();
+        let file = |path: &str| Some(Resolved::File(output.join(path)));
+
+        assert_eq!(resolve(&out, "/"), file("index.html"));
+        assert_eq!(resolve(&out, "/style.css"), file("style.css"));
+        assert_eq!(resolve(&out, "/./style.css"), file("style.css"));
+        assert_eq!(resolve(&out, "/blog/"), file("blog/index.html"));
+        assert_eq!(resolve(&out, "/blog"), Some(Resolved::Directory));
+        assert_eq!(resolve(&out, "/missing"), None);
+
+        assert_eq!(resolve(&out, "/../secret.txt"), None);
+        assert_eq!(resolve(&out, "/blog/../../secret.txt"), None);
+        assert_eq!(resolve(&out, &decode("/%2e%2e/secret.txt")), None);
+        assert_eq!(resolve(&out, &decode("/%2E%2E%2Fsecret.txt")), None);
+
+        _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(decode("/a%20b.html"), "/a b.html");
+        assert_eq!(decode("/%2e%2E/x"), "/../x");
+        assert_eq!(decode("/caf%C3%A9/"), "/café/");
+        assert_eq!(decode("/100%"), "/100%");
+        assert_eq!(decode("/%zz%4"), "/%zz%4");
+    }
+
+    #[test]
+    fn script_goes_before_body_end() {
+        let inject = |html: &str| String::from_utf8(inject_script(html.into())).unwrap();
+
+        assert_eq!(
+            inject("<body><p>&lt;/body&gt;</p></body></html>"),
+            format!("<body><p>&lt;/body&gt;</p>{RELOAD_SCRIPT}</body></html>")
+        );
+        assert_eq!(
+            inject("<body>a</body> <body>b</body>"),
+            format!("<body>a</body> <body>b{RELOAD_SCRIPT}</body>")
+        );
+        assert_eq!(inject("<p>bare</p>"), format!("<p>bare</p>{RELOAD_SCRIPT}"));
+    }
+}
@@ -0,0 +1,54 @@
+use serde_yaml::{Mapping, Value};
+
+/// Site-wide configuration, from the `[site]` table in `rules.toml`.
+///
+/// The well-known keys are `title`, `base_url`, `language` and `author`, but
+/// any other keys are kept as well.
+#[derive(Debug, Clone, Default)]
+pub struct Site {
+    data: toml::Table,
+}
+
+impl Site {
+    pub fn new(data: toml::Table) -> Self {
+        Self { data }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.data.get(key).and_then(toml::Value::as_str)
+    }
+
+    pub fn set(&mut self, key: &str, value: String) {
+        self.data
+            .insert(key.to_string(), toml::Value::String(value));
+    }
+
+    pub fn base_url(&self) -> Option<&str> {
+        self.get("base_url")
+    }
+
+    /// Replaces `{site.<key>}` with the value of every non-table key, plus
+    /// `{base_url}` as a shorthand for `{site.base_url}`.
+    pub fn substitute(&self, string: &str) -> String {
+        let mut string = string.replace("{base_url}", self.base_url().unwrap_or_default());
+        for (key, value) in &self.data {
+            let value = match value {
+                toml::Value::String(s) => s.clone(),
+                toml::Value::Table(_) | toml::Value::Array(_) => continue,
+                v => v.to_string(),
+            };
+
+            string = string.replace(&format!("{{site.{key}}}"), &value);
+        }
+
+        string
+    }
+
+    /// The configuration as template data.
+    pub fn to_mapping(&self) -> Mapping {
+        match serde_yaml::to_value(&self.data) {
+            Ok(Value::Mapping(m)) => m,
+            _ => Mapping::new(),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::fs::{create_dir_all, metadata, write};
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::debug;
+use serde_yaml::Value;
+
+use crate::filepath::FilePath;
+use crate::helpers::{escape_xml, parse_date, url_join};
+use crate::page::{self, Page};
+use crate::site::Site;
+
+pub const DEFAULT_PATH: &str = "sitemap.xml";
+
+/// A `sitemap.xml` listing every HTML page, and a `robots.txt` pointing to it.
+#[derive(Debug, Clone)]
+pub struct Sitemap {
+    path: String,
+    robots: bool,
+}
+
+impl Sitemap {
+    pub fn new(path: Option<String>, robots: bool) -> Self {
+        Self {
+            path: path.unwrap_or_else(|| String::from(DEFAULT_PATH)),
+            robots,
+        }
+    }
+
+    /// Writes the sitemap (and `robots.txt`, unless `has_robots`) into
+    /// `output`. `pages` are the built content files; `others` are any other
+    /// pages, relative to the output directory, with the file to take their
+    /// last modification time from. Returns the paths of the written files.
+    pub fn generate(
+        &self,
+        pages: &[Page],
+        others: &[(FilePath, Option<FilePath>)],
+        site: &Site,
+        output: &str,
+        has_robots: bool,
+    ) -> Result<Vec<FilePath>, String> {
+        let base_url = site.base_url().unwrap_or_default();
+
+        let mut entries = Vec::new();
+        for page in pages {
+            if !is_html(&page.output)
+                || matches!(page.meta.get("sitemap"), Some(Value::Bool(false)))
+            {
+                continue;
+            }
+
+            let lastmod = ["updated", "date"]
+                .iter()
+                .find_map(|k| page.meta.get(*k).and_then(Value::as_str))
+                .and_then(parse_date)
+                .map(|d| d.with_timezone(&Utc))
+                .or_else(|| mtime(&page.source));
+
+            entries.push((page.url(), lastmod));
+        }
+
+        for (path, source) in others {
+            if is_html(path) {
+                entries.push((page::url(path), source.as_ref().and_then(mtime)));
+            }
+        }
+
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries.dedup_by(|a, b| a.0 == b.0);
+
+        let mut xml = String::from(concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            "\n",
+            r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#,
+            "\n"
+        ));
+
+        for (url, lastmod) in &entries {
+            xml.push_str("<url>\n");
+            xml.push_str(&format!(
+                "<loc>{}</loc>\n",
+                escape_xml(&url_join(&[base_url, url]))
+            ));
+            if let Some(lastmod) = lastmod {
+                xml.push_str(&format!(
+                    "<lastmod>{}</lastmod>\n",
+                    lastmod.to_rfc3339_opts(SecondsFormat::Secs, true)
+                ));
+            }
+            xml.push_str("</url>\n");
+        }
+
+        xml.push_str("</urlset>\n");
+
+        let sitemap = FilePath::from_str(self.path.trim_start_matches('/'))
+            .map_err(|e| format!("Sitemap path {} invalid: {e}", self.path))?;
+
+        let mut files = vec![write_file(output, &sitemap, xml)?];
+        if self.robots && !has_robots {
+            let robots = format!(
+                "User-agent: *\nAllow: /\n\nSitemap: {}\n",
+                url_join(&[base_url, &self.path])
+            );
+
+            files.push(write_file(
+                output,
+                &FilePath::from_str("robots.txt").unwrap(),
+                robots,
+            )?);
+        }
+
+        Ok(files)
+    }
+}
+
+fn is_html(path: &FilePath) -> bool {
+    matches!(path.ext().as_str(), "html" | "htm")
+}
+
+fn mtime(path: &FilePath) -> Option<DateTime<Utc>> {
+    metadata(path.full())
+        .and_then(|m| m.modified())
+        .ok()
+        .map(DateTime::from)
+}
+
+fn write_file(output: &str, path: &FilePath, data: String) -> Result<FilePath, String> {
+    let out = path.clone().prefix(output);
+    create_dir_all(out.dir()).map_err(|e| format!("Failed to create {}: {e}", out.dir()))?;
+
+    debug!("Writing `{}`", out.full());
+    write(out.full(), data).map_err(|e| format!("Failed to write {}: {e}", out.full()))?;
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_yaml::Mapping;
+
+    use super::*;
+
+    fn page(output: &str, meta: &str) -> Page {
+        Page {
+            source: FilePath::from_str("content/missing.md").unwrap(),
+            output: FilePath::from_str(output).unwrap(),
+            meta: serde_yaml::from_str::<Mapping>(meta).unwrap(),
+            content: String::new(),
+        }
+    }
+
+    #[test]
+    fn sitemap_and_robots() {
+        let pages = [
+            page("index.html", "title: Home\ndate: 2024-01-02"),
+            page(
+                "blog/fish&chips/index.html",
+                "date: 2024-01-02\nupdated: 2024-03-04T05:06:07+01:00",
+            ),
+            page("drafts/wip/index.html", "sitemap: false"),
+            page("notes.txt", "title: Notes"),
+            page("about/index.html", "title: About"),
+        ];
+        let others = [
+            (FilePath::from_str("tags/rust/index.html").unwrap(), None),
+            (FilePath::from_str("index.html").unwrap(), None),
+        ];
+        let site = Site::new(toml::from_str("base_url = \"https://example.com/\"").unwrap());
+
+        let out = std::env::temp_dir().join(format!("rssg-sitemap-{}", std::process::id()));
+        let out = out.display().to_string();
+        let read = |file: &FilePath| std::fs::read_to_string(file.full()).unwrap();
+
+        let sitemap = Sitemap::new(None, true);
+        let files = sitemap
+            .generate(&pages, &others, &site, &out, false)
+            .unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            read(&files[0]),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+<url>
+<loc>https://example.com/</loc>
+<lastmod>2024-01-02T00:00:00Z</lastmod>
+</url>
+<url>
+<loc>https://example.com/about/</loc>
+</url>
+<url>
+<loc>https://example.com/blog/fish&amp;chips/</loc>
+<lastmod>2024-03-04T04:06:07Z</lastmod>
+</url>
+<url>
+<loc>https://example.com/tags/rust/</loc>
+</url>
+</urlset>
+"#
+        );
+        assert_eq!(
+            read(&files[1]),
+            "User-agent: *\nAllow: /\n\nSitemap: https://example.com/sitemap.xml\n"
+        );
+
+        // A `robots.txt` of the site's own is left alone
+        let sitemap = Sitemap::new(Some("/maps/site.xml".into()), true);
+        let files = sitemap
+            .generate(&pages, &others, &site, &out, true)
+            .unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].full().ends_with("/maps/site.xml"));
+
+        _ = std::fs::remove_dir_all(&out);
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::create_dir_all;
+use std::str::FromStr;
+
+use log::debug;
+use serde_yaml::{Mapping, Value};
+
+use crate::filepath::FilePath;
+use crate::helpers::slugify;
+use crate::page::{self, Page};
+use crate::paginate::Paginate;
+use crate::template::{TemplateErr, Templates};
+
+// The slug of terms without any letters or numbers, like `!!!`
+const EMPTY_SLUG: &str = "term";
+
+/// A way of grouping pages by a frontmatter key, like `tags`, with a generated
+/// page for every term (and optionally an overview of all terms).
+#[derive(Debug, Clone)]
+pub struct Taxonomy {
+    name: String,
+    collection: Option<String>,
+    template: String,
+    output: String,
+    overview_template: Option<String>,
+    overview_output: Option<String>,
+    paginate: Option<Paginate>,
+}
+
+struct Term<'a> {
+    name: String,
+    slug: String,
+    output: FilePath,
+    pages: Vec<&'a Page>,
+}
+
+impl Term<'_> {
+    fn to_value(&self, with_pages: bool) -> Value {
+        let mut data = Mapping::new();
+        data.insert("name".into(), self.name.clone().into());
+        data.insert("slug".into(), self.slug.clone().into());
+        data.insert("url".into(), page::url(&self.output).into());
+        data.insert("count".into(), self.pages.len().into());
+
+        if with_pages {
+            data.insert("pages".into(), pages_value(&self.pages));
+        }
+
+        Value::Mapping(data)
+    }
+}
+
+impl Taxonomy {
+    pub fn new(
+        name: String,
+        collection: Option<String>,
+        template: String,
+        output: String,
+        overview: Option<(String, String)>,
+        paginate: Option<Paginate>,
+    ) -> Self {
+        let (overview_template, overview_output) = overview.unzip();
+        Self {
+            name,
+            collection,
+            template,
+            output,
+            overview_template,
+            overview_output,
+            paginate,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The collection whose pages are grouped, if not every page.
+    pub fn collection(&self) -> Option<&str> {
+        self.collection.as_deref()
+    }
+
+    /// Renders the page for every term, plus the overview page, into
+    /// `output`. Returns the paths of every generated file.
+    pub fn generate(
+        &self,
+        pages: &[&Page],
+        templates: &Templates,
+        output: &str,
+    ) -> Result<Vec<FilePath>, TemplateErr> {
+        let terms = self.terms(pages)?;
+        let all_terms = Value::Sequence(terms.iter().map(|t| t.to_value(true)).collect());
+
+        let mut generated = Vec::with_capacity(terms.len() + 1);
+        for term in &terms {
+            let mut vars = Mapping::new();
+            vars.insert("taxonomy".into(), self.name.clone().into());
+            vars.insert("term".into(), term.to_value(false));
+            vars.insert("pages".into(), pages_value(&term.pages));
+            vars.insert("terms".into(), all_terms.clone());
+
+            let outputs = match &self.paginate {
+                Some(paginate) => paginate
+                    .paginate(&term.output, &page_values(&term.pages))
+                    .map_err(TemplateErr::InvalidPath)?,
+                None => vec![(term.output.clone(), Value::Null)],
+            };
+
+            for (out, paginator) in outputs {
+                let mut vars = vars.clone();
+                if !paginator.is_null() {
+                    vars.insert("paginator".into(), paginator);
+                }
+
+                let out = out.prefix(output);
+                debug!("Generating `{}` for term `{}`", out.full(), term.name);
+                render(templates, &self.template, &out, &vars)?;
+                generated.push(out);
+            }
+        }
+
+        if let (Some(template), Some(out)) = (&self.overview_template, &self.overview_output) {
+            let out = FilePath::from_str(out)
+                .map_err(|_| TemplateErr::InvalidPath(out.clone()))?
+                .prefix(output);
+
+            let mut vars = Mapping::new();
+            vars.insert("taxonomy".into(), self.name.clone().into());
+            vars.insert("terms".into(), all_terms);
+
+            debug!("Generating `{}` for taxonomy `{}`", out.full(), self.name);
+            render(templates, template, &out, &vars)?;
+            generated.push(out);
+        }
+
+        Ok(generated)
+    }
+
+    // Groups the pages by term, keeping their order. Terms differing only in
+    // case, like `Rust` and `rust`, are the same term, named as they're first
+    // seen. Terms are sorted by name, ignoring case, and get unique slugs.
+    fn terms<'a>(&self, pages: &[&'a Page]) -> Result<Vec<Term<'a>>, TemplateErr> {
+        let mut terms: BTreeMap<String, (String, Vec<&Page>)> = BTreeMap::new();
+        for page in pages {
+            let names = match page.meta.get(self.name.as_str()) {
+                Some(Value::Sequence(seq)) => seq.iter().filter_map(term_name).collect(),
+                Some(value) => term_name(value).into_iter().collect(),
+                None => Vec::new(),
+            };
+
+            for name in names {
+                let (_, term) = terms
+                    .entry(name.to_lowercase())
+                    .or_insert_with(|| (name, Vec::new()));
+                if !term.iter().any(|p| p.source == page.source) {
+                    term.push(page);
+                }
+            }
+        }
+
+        let mut slugs = HashSet::new();
+        terms
+            .into_values()
+            .map(|(name, pages)| {
+                let slug = unique_slug(&name, &mut slugs);
+                let out = self.output.replace("{term}", &slug);
+                let output =
+                    FilePath::from_str(&out).map_err(|_| TemplateErr::InvalidPath(out.clone()))?;
+
+                Ok(Term {
+                    name,
+                    slug,
+                    output,
+                    pages,
+                })
+            })
+            .collect()
+    }
+}
+
+// Slugifies `name`, numbering it if the slug is already in `used`, e.g. `c-1`
+// for `C++` after `C`
+fn unique_slug(name: &str, used: &mut HashSet<String>) -> String {
+    let mut slug = slugify(name);
+    if slug.is_empty() {
+        slug = String::from(EMPTY_SLUG);
+    }
+
+    let mut unique = slug.clone();
+    let mut n = 1;
+    while !used.insert(unique.clone()) {
+        unique = format!("{slug}-{n}");
+        n += 1;
+    }
+
+    unique
+}
+
+fn term_name(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn page_values(pages: &[&Page]) -> Vec<Value> {
+    pages.iter().map(|p| p.to_value()).collect()
+}
+
+fn pages_value(pages: &[&Page]) -> Value {
+    Value::Sequence(page_values(pages))
+}
+
+fn render(
+    templates: &Templates,
+    template: &str,
+    out: &FilePath,
+    vars: &Mapping,
+) -> Result<(), TemplateErr> {
+    create_dir_all(out.dir()).map_err(TemplateErr::FailedToWrite)?;
+    templates.render(template, out.full(), vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(name: &str, tags: &[&str]) -> Page {
+        let mut meta = Mapping::new();
+        meta.insert(
+            "tags".into(),
+            Value::Sequence(tags.iter().map(|&t| t.into()).collect()),
+        );
+
+        Page {
+            source: FilePath::from_str(&format!("content/{name}.md")).unwrap(),
+            output: FilePath::from_str(&format!("{name}/index.html")).unwrap(),
+            meta,
+            content: String::new(),
+        }
+    }
+
+    fn tags() -> Taxonomy {
+        Taxonomy::new(
+            String::from("tags"),
+            None,
+            String::from("tag.html"),
+            String::from("tags/{term}/index.html"),
+            None,
+            None,
+        )
+    }
+
+    fn summary(terms: &[Term]) -> Vec<(String, String, Vec<String>)> {
+        terms
+            .iter()
+            .map(|t| {
+                let pages = t.pages.iter().map(|p| p.source.name()).collect();
+                (t.name.clone(), t.output.full(), pages)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn terms_sorted_ignoring_case() {
+        let pages = [page("a", &["zig", "Go"]), page("b", &["go", "Ada"])];
+        let pages = pages.iter().collect::<Vec<_>>();
+
+        let terms = tags().terms(&pages).unwrap();
+        assert_eq!(
+            summary(&terms),
+            [
+                ("Ada".into(), "tags/ada/index.html".into(), vec!["b".into()]),
+                (
+                    "Go".into(),
+                    "tags/go/index.html".into(),
+                    vec!["a".into(), "b".into()]
+                ),
+                ("zig".into(), "tags/zig/index.html".into(), vec!["a".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn terms_differing_in_case_are_merged() {
+        let pages = [
+            page("a", &["Rust"]),
+            page("b", &["rust"]),
+            page("c", &["RUST", "rust"]),
+        ];
+        let pages = pages.iter().collect::<Vec<_>>();
+
+        let terms = tags().terms(&pages).unwrap();
+        assert_eq!(
+            summary(&terms),
+            [(
+                "Rust".into(),
+                "tags/rust/index.html".into(),
+                vec!["a".into(), "b".into(), "c".into()]
+            )]
+        );
+    }
+
+    #[test]
+    fn colliding_slugs_are_kept_apart() {
+        let pages = [page("a", &["C++", "!!!", "c-1"]), page("b", &["C", "???"])];
+        let pages = pages.iter().collect::<Vec<_>>();
+
+        let terms = tags().terms(&pages).unwrap();
+        assert_eq!(
+            summary(&terms),
+            [
+                (
+                    "!!!".into(),
+                    "tags/term/index.html".into(),
+                    vec!["a".into()]
+                ),
+                (
+                    "???".into(),
+                    "tags/term-1/index.html".into(),
+                    vec!["b".into()]
+                ),
+                ("C".into(), "tags/c/index.html".into(), vec!["b".into()]),
+                ("C++".into(), "tags/c-1/index.html".into(), vec!["a".into()]),
+                (
+                    "c-1".into(),
+                    "tags/c-1-1/index.html".into(),
+                    vec!["a".into()]
+                ),
+            ]
+        );
+    }
+}
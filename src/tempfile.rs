@@ -3,11 +3,14 @@ use std::hash::{Hash, Hasher};
 
 use crate::filepath::FilePath;
 
+/// The directory builds put temporary files in.
+pub const ROOT: &str = "temp";
+
 // example:
 //   source: `default.html`
 //     file: `content/home/index.html`
 //   output: `temp/8264099790966940254/index.html`
-pub fn tempdir(source: &str, filepath: &FilePath) -> FilePath {
+pub fn tempdir(root: &str, source: &str, filepath: &FilePath) -> FilePath {
     let mut s = DefaultHasher::new();
     source.hash(&mut s);
     filepath.full().hash(&mut s);
@@ -15,5 +18,5 @@ pub fn tempdir(source: &str, filepath: &FilePath) -> FilePath {
     let mut new = FilePath::new();
     new.name = filepath.name();
     new.ext = filepath.ext();
-    new.prefix(s.finish()).prefix("temp")
+    new.prefix(s.finish()).prefix(root)
 }
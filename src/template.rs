@@ -1,15 +1,20 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::Display;
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
+use std::fs::{read, read_dir, File, OpenOptions};
+use std::io::{self, Read, Write};
 use std::path::Path;
 use std::str::FromStr;
 
+use fancy_regex::Regex;
 use handlebars::{Handlebars, RenderError};
+use serde::ser::{Serialize, SerializeMap, Serializer};
 use serde_yaml::{Mapping, Value};
 
 use crate::filepath::FilePath;
+use crate::helpers;
+use crate::paginate::Paginate;
 
 const VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
 
@@ -21,6 +26,8 @@ pub enum TemplateErr {
     InvalidTemplate(RenderError),
     FileNotFound(String),
 
+    InvalidPartial(String, String),
+
     FailedToReadTemplate(std::io::Error),
     FailedToReadFile(std::io::Error),
 
@@ -36,6 +43,8 @@ impl Display for TemplateErr {
             Self::InvalidTemplate(e) => write!(f, "Failed to apply template: {e}"),
             Self::FileNotFound(file) => write!(f, "Failed to find input file {file}"),
 
+            Self::InvalidPartial(name, e) => write!(f, "Failed to register partial {name}: {e}"),
+
             Self::FailedToReadTemplate(e) => write!(f, "Failed to read template file: {e}"),
             Self::FailedToReadFile(e) => write!(f, "Failed to read input file: {e}"),
 
@@ -46,17 +55,307 @@ impl Display for TemplateErr {
 
 impl Error for TemplateErr {}
 
-pub fn apply_template<T, F, O>(
-    template: T,
-    file: F,
-    out: O,
-    yaml: &Mapping,
-) -> Result<(), TemplateErr>
-where
-    T: AsRef<Path>,
-    F: AsRef<Path>,
-    O: AsRef<Path>,
-{
+/// The template registry for a build, holding every partial and helper.
+pub struct Templates {
+    registry: Handlebars<'static>,
+    /// The file behind each partial, relative to the templates directory.
+    files: HashMap<String, String>,
+    /// The contents of each file, by its path relative to the templates
+    /// directory.
+    sources: HashMap<String, String>,
+    /// Partials that failed to register, by file, with their name and error.
+    broken: HashMap<String, (String, String)>,
+    site: Mapping,
+    collections: Mapping,
+    pagination: HashMap<String, Paginate>,
+}
+
+impl Templates {
+    /// Registers every file under `dir` as a partial, named after its path
+    /// relative to `dir` without the extension (`partials/header.html` becomes
+    /// `partials/header`). Files are also registered under their bare name
+    /// (`header`), unless that would be ambiguous.
+    ///
+    /// Files that aren't UTF-8, like images, are skipped. A partial that fails
+    /// to parse is only an error once a template using it is applied.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, TemplateErr> {
+        let mut registry = Handlebars::new();
+        helpers::register(&mut registry);
+
+        let mut partials = Vec::new();
+        visit_dirs(dir.as_ref(), dir.as_ref(), &mut partials)
+            .map_err(TemplateErr::FailedToReadTemplate)?;
+
+        let mut files = HashMap::new();
+        let mut broken = HashMap::new();
+        for (name, file, data) in &partials {
+            if let Err(e) = registry.register_partial(name, data) {
+                broken.insert(file.clone(), (name.clone(), e.to_string()));
+            }
+
+            files.insert(name.clone(), file.clone());
+        }
+
+        let sources = partials
+            .iter()
+            .map(|(_, file, data)| (file.clone(), data.clone()))
+            .collect();
+
+        for (name, file, data) in &partials {
+            let Some((_, short)) = name.rsplit_once('/') else {
+                continue;
+            };
+
+            let ambiguous = partials
+                .iter()
+                .filter(|(n, _, _)| n == short || n.ends_with(&format!("/{short}")))
+                .count()
+                > 1;
+
+            if !ambiguous && !broken.contains_key(file) {
+                registry.register_partial(short, data).unwrap();
+                files.insert(short.to_string(), file.clone());
+            }
+        }
+
+        Ok(Self {
+            registry,
+            files,
+            sources,
+            broken,
+            site: Mapping::new(),
+            collections: Mapping::new(),
+            pagination: HashMap::new(),
+        })
+    }
+
+    /// Every template file `template` uses, including itself and the partials
+    /// it includes (and the partials they include, and so on), relative to the
+    /// templates directory.
+    ///
+    /// Only partials named literally, like `{{> header}}`, can be found.
+    pub fn dependencies(&self, template: &str) -> Vec<String> {
+        let mut found = vec![template.to_string()];
+        self.find_partials(&self.source(template), &mut found);
+        found
+    }
+
+    // Adds the files of the partials `data` includes, and the partials they
+    // include, and so on, to `found`
+    fn find_partials(&self, data: &str, found: &mut Vec<String>) {
+        let partial = Regex::new(r#"\{\{~?#?>\s*"?([^\s"}~]+)"#).unwrap();
+
+        let mut i = found.len();
+        let mut data = Cow::Borrowed(data);
+        loop {
+            for name in partial.captures_iter(&data).filter_map(Result::ok) {
+                if let Some(file) = self.files.get(&name[1]) {
+                    if !found.contains(file) {
+                        found.push(file.clone());
+                    }
+                }
+            }
+
+            let Some(file) = found.get(i) else {
+                break;
+            };
+
+            data = self.source(file);
+            i += 1;
+        }
+    }
+
+    // The contents of a template file, relative to the templates directory
+    fn source(&self, file: &str) -> Cow<'_, str> {
+        match self.sources.get(file) {
+            Some(data) => Cow::Borrowed(data),
+            None => Cow::Owned(read_template(file).unwrap_or_default()),
+        }
+    }
+
+    // Fails if `template`, or a partial it includes, couldn't be registered
+    fn check_partials<T: AsRef<Path>>(&self, template: T) -> Result<(), TemplateErr> {
+        if self.broken.is_empty() {
+            return Ok(());
+        }
+
+        for file in self.dependencies(&template.as_ref().display().to_string()) {
+            if let Some((name, e)) = self.broken.get(&file) {
+                return Err(TemplateErr::InvalidPartial(name.clone(), e.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `template`, or a partial it includes, refers to the variable
+    /// `name`, like `site`. See [`mentions`].
+    pub fn uses(&self, template: &str, name: &str) -> bool {
+        self.dependencies(template)
+            .iter()
+            .any(|file| mentions(&self.source(file), name))
+    }
+
+    /// Sets the data exposed to every template as `site`.
+    pub fn set_site(&mut self, site: Mapping) {
+        self.site = site;
+    }
+
+    /// Sets the pages exposed to every template as `collections.<name>`, and
+    /// how to paginate each collection.
+    pub fn set_collections(&mut self, collections: Mapping, pagination: HashMap<String, Paginate>) {
+        self.collections = collections;
+        self.pagination = pagination;
+    }
+
+    /// Splits a collection into pages for a listing page whose first output is
+    /// `first`. See [`Paginate::paginate`].
+    pub fn paginate(
+        &self,
+        collection: &str,
+        first: &FilePath,
+    ) -> Result<Vec<(FilePath, Value)>, String> {
+        let Some(Value::Sequence(items)) = self.collections.get(collection) else {
+            return Err(format!("The collection `{collection}` does not exist"));
+        };
+
+        match self.pagination.get(collection) {
+            Some(paginate) => paginate.paginate(first, items),
+            None => Paginate::new(usize::MAX, None).paginate(first, items),
+        }
+    }
+
+    pub fn apply<T, F, O>(
+        &self,
+        template: T,
+        file: F,
+        out: O,
+        yaml: &Mapping,
+    ) -> Result<(), TemplateErr>
+    where
+        T: AsRef<Path>,
+        F: AsRef<Path>,
+        O: AsRef<Path>,
+    {
+        self.check_partials(&template)?;
+        let mut template_data = read_template(template)?;
+
+        if !file.as_ref().exists() {
+            return Err(TemplateErr::FileNotFound(
+                file.as_ref().display().to_string(),
+            ));
+        }
+
+        let data = match File::open(&file) {
+            Ok(mut f) => {
+                let mut buf = String::new();
+
+                if let Err(e) = f.read_to_string(&mut buf) {
+                    return Err(TemplateErr::FailedToReadFile(e));
+                }
+
+                buf
+            }
+
+            Err(e) => return Err(TemplateErr::FailedToReadFile(e)),
+        };
+
+        template_data = template_data.replace("{{data}}", &data);
+        template_data = template_data.replace("{{ data }}", &data);
+
+        self.render_to(&template_data, yaml, out)
+    }
+
+    /// Renders a template that has no content file behind it, such as a
+    /// generated listing page. `{{data}}` is left empty.
+    pub fn render<T, O>(&self, template: T, out: O, vars: &Mapping) -> Result<(), TemplateErr>
+    where
+        T: AsRef<Path>,
+        O: AsRef<Path>,
+    {
+        self.check_partials(&template)?;
+        let template_data = read_template(template)?
+            .replace("{{data}}", "")
+            .replace("{{ data }}", "");
+
+        self.render_to(&template_data, vars, out)
+    }
+
+    fn render_to<O: AsRef<Path>>(
+        &self,
+        template_data: &str,
+        yaml: &Mapping,
+        out: O,
+    ) -> Result<(), TemplateErr> {
+        let vars = Vars {
+            page: yaml,
+            site: &self.site,
+            collections: &self.collections,
+        };
+
+        let rendered = self
+            .registry
+            .render_template(template_data, &vars)
+            .map_err(TemplateErr::InvalidTemplate)?;
+
+        match OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&out)
+        {
+            Ok(mut f) => {
+                if let Err(e) = f.write_all(rendered.as_bytes()) {
+                    return Err(TemplateErr::FailedToWrite(e));
+                }
+
+                Ok(())
+            }
+
+            Err(e) => Err(TemplateErr::FailedToWrite(e)),
+        }
+    }
+}
+
+// The variables a template is rendered with
+struct Vars<'a> {
+    page: &'a Mapping,
+    site: &'a Mapping,
+    collections: &'a Mapping,
+}
+
+impl Serialize for Vars<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        for (key, value) in self.page {
+            if !matches!(key.as_str(), Some("version" | "site" | "collections")) {
+                map.serialize_entry(key, value)?;
+            }
+        }
+
+        map.serialize_entry("version", VERSION.unwrap_or("unknown"))?;
+        map.serialize_entry("site", self.site)?;
+        map.serialize_entry("collections", self.collections)?;
+
+        map.end()
+    }
+}
+
+/// Whether a Handlebars expression in `data` refers to the variable `name`,
+/// like `site` does in `{{site.title}}` or `{{#each site.authors}}`.
+pub fn mentions(data: &str, name: &str) -> bool {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    data.split("{{").skip(1).any(|expr| {
+        let expr = expr.split("}}").next().unwrap_or_default();
+        expr.match_indices(name).any(|(i, _)| {
+            !expr[..i].chars().next_back().is_some_and(is_ident)
+                && !expr[i + name.len()..].chars().next().is_some_and(is_ident)
+        })
+    })
+}
+
+// Reads a template, relative to the `templates` directory.
+fn read_template<T: AsRef<Path>>(template: T) -> Result<String, TemplateErr> {
     let t = FilePath::from_str(&template.as_ref().display().to_string())
         .map_err(|_| TemplateErr::InvalidPath(template.as_ref().display().to_string()))?
         .prefix("templates")
@@ -70,13 +369,7 @@ where
         ));
     }
 
-    if !file.as_ref().exists() {
-        return Err(TemplateErr::FileNotFound(
-            file.as_ref().display().to_string(),
-        ));
-    }
-
-    let mut template_data = match File::open(template) {
+    match File::open(template) {
         Ok(mut f) => {
             let mut buf = String::new();
 
@@ -84,51 +377,80 @@ where
                 return Err(TemplateErr::FailedToReadTemplate(e));
             }
 
-            buf
+            Ok(buf)
         }
 
-        Err(e) => return Err(TemplateErr::FailedToReadTemplate(e)),
-    };
+        Err(e) => Err(TemplateErr::FailedToReadTemplate(e)),
+    }
+}
 
-    let data = match File::open(&file) {
-        Ok(mut f) => {
-            let mut buf = String::new();
+// Finds every template under `dir`, as (partial name, path relative to `root`,
+// contents), skipping files that aren't UTF-8.
+fn visit_dirs(
+    root: &Path,
+    dir: &Path,
+    partials: &mut Vec<(String, String, String)>,
+) -> io::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
 
-            if let Err(e) = f.read_to_string(&mut buf) {
-                return Err(TemplateErr::FailedToReadFile(e));
-            }
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            visit_dirs(root, &path, partials)?;
+        } else {
+            let file = path.strip_prefix(root).unwrap_or(&path);
+            let name = file.with_extension("").display().to_string();
 
-            buf
+            if let Ok(data) = String::from_utf8(read(&path)?) {
+                partials.push((name, file.display().to_string(), data));
+            }
         }
+    }
+
+    Ok(())
+}
 
-        Err(e) => return Err(TemplateErr::FailedToReadFile(e)),
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    template_data = template_data.replace("{{data}}", &data);
-    template_data = template_data.replace("{{ data }}", &data);
+    #[test]
+    fn mentions_variables() {
+        assert!(mentions("<h1>{{site.title}}</h1>", "site"));
+        assert!(mentions("{{#each site.authors}}{{name}}{{/each}}", "site"));
+        assert!(mentions("{{ @root.site.title }}", "site"));
+        assert!(mentions("{{lookup collections \"posts\"}}", "collections"));
 
-    let mut vars: HashMap<Value, Value> = HashMap::from_iter(yaml.clone());
-    vars.insert("version".into(), VERSION.unwrap_or("unknown").into());
+        assert!(!mentions("site.title", "site"));
+        assert!(!mentions("{{website}} {{site_name}} {{site-name}}", "site"));
+        assert!(!mentions("{{title}} }} site {{", "site"));
+    }
 
-    let reg = Handlebars::new();
-    template_data = reg
-        .render_template(&template_data, &vars)
-        .map_err(TemplateErr::InvalidTemplate)?;
+    #[test]
+    fn shared_variables_override_page() {
+        let mut templates = Templates::load("no-templates").unwrap();
+        let mut site = Mapping::new();
+        site.insert("title".into(), "Site".into());
+        templates.set_site(site);
 
-    match OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(&out)
-    {
-        Ok(mut f) => {
-            if let Err(e) = f.write_all(template_data.as_bytes()) {
-                return Err(TemplateErr::FailedToWrite(e));
-            }
+        let mut page = Mapping::new();
+        page.insert("title".into(), "Page".into());
+        page.insert("site".into(), "overridden".into());
 
-            Ok(())
-        }
+        let out = std::env::temp_dir().join(format!("rssg-vars-{}", std::process::id()));
+        let render = |template: &str| {
+            templates.render_to(template, &page, &out).unwrap();
+            std::fs::read_to_string(&out).unwrap()
+        };
+
+        assert_eq!(
+            render("{{#each this}}{{@key}} {{/each}}"),
+            "collections site title version "
+        );
+        assert_eq!(render("{{site.title}}: {{title}}"), "Site: Page");
 
-        Err(e) => Err(TemplateErr::FailedToWrite(e)),
+        _ = std::fs::remove_file(&out);
     }
 }
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use std::thread::sleep;
+use std::time::Duration;
+
+use log::{debug, info};
+
+use crate::cache;
+use crate::filepath::FilePath;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Polls the given paths for changes, calling `rebuild` with the list of
+/// changed (created, modified, or deleted) files whenever something changes.
+///
+/// Never returns; the user is expected to stop the process manually.
+pub fn watch<F: FnMut(&[FilePath])>(paths: &[&str], mut rebuild: F) -> ! {
+    let mut snapshot = cache::snapshot(paths, &HashMap::new());
+
+    loop {
+        sleep(POLL_INTERVAL);
+
+        // Only files whose metadata changed get hashed again
+        let new = cache::snapshot(paths, &snapshot);
+        let mut changed = new
+            .iter()
+            .filter(|(file, stamp)| snapshot.get(file).map(|s| s.hash) != Some(stamp.hash))
+            .map(|(file, _)| file.clone())
+            .collect::<Vec<_>>();
+
+        changed.extend(snapshot.keys().filter(|f| !new.contains_key(f)).cloned());
+        snapshot = new;
+        if changed.is_empty() {
+            continue;
+        }
+
+        for file in &changed {
+            debug!("Detected change in `{}`", file.full());
+        }
+
+        info!("Detected {} changed file(s), rebuilding", changed.len());
+        rebuild(&changed);
+    }
+}